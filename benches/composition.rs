@@ -0,0 +1,33 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use lopin::{async_pipeline, pipeline, typed::{self, AsyncStaticPipeline, StaticPipeline}, RawAsyncPipeline, RawPipeline};
+
+fn sync_chain(c: &mut Criterion) {
+  let dynamic = pipeline(|v: u64| Ok::<_, ()>(v + 1)) & pipeline(|v: u64| Ok(v * 3)) & pipeline(|v: u64| Ok(v ^ 7)) & pipeline(|v: u64| Ok(v - 1));
+  let fixed = typed::pipeline(|v: u64| Ok::<_, ()>(v + 1)) & typed::pipeline(|v: u64| Ok(v * 3)) & typed::pipeline(|v: u64| Ok(v ^ 7)) & typed::pipeline(|v: u64| Ok(v - 1));
+  let mut group = c.benchmark_group("sync_chain");
+  group.bench_function("dynamic", |b| b.iter(|| RawPipeline::run(&dynamic, black_box(10))));
+  group.bench_function("typed", |b| b.iter(|| fixed.run(black_box(10))));
+  group.finish();
+}
+
+fn sync_fallback(c: &mut Criterion) {
+  let dynamic = pipeline(|v: u64| if v > 100 { Ok(v) } else { Err(()) }) | pipeline(|v: u64| if v > 10 { Ok(v) } else { Err(()) }) | pipeline(|v: u64| Ok(v));
+  let fixed = typed::pipeline(|v: u64| if v > 100 { Ok(v) } else { Err(()) }) | typed::pipeline(|v: u64| if v > 10 { Ok(v) } else { Err(()) }) | typed::pipeline(|v: u64| Ok(v));
+  let mut group = c.benchmark_group("sync_fallback");
+  group.bench_function("dynamic", |b| b.iter(|| RawPipeline::run(&dynamic, black_box(5))));
+  group.bench_function("typed", |b| b.iter(|| fixed.run(black_box(5))));
+  group.finish();
+}
+
+fn async_chain(c: &mut Criterion) {
+  let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+  let dynamic = async_pipeline(|v: u64| async move { Ok::<_, ()>(v + 1) }) & async_pipeline(|v: u64| async move { Ok(v * 3) }) & async_pipeline(|v: u64| async move { Ok(v - 1) });
+  let fixed = typed::async_pipeline(|v: u64| async move { Ok::<_, ()>(v + 1) }) & typed::async_pipeline(|v: u64| async move { Ok(v * 3) }) & typed::async_pipeline(|v: u64| async move { Ok(v - 1) });
+  let mut group = c.benchmark_group("async_chain");
+  group.bench_function("dynamic", |b| b.iter(|| runtime.block_on(RawAsyncPipeline::async_run(&dynamic, black_box(10)))));
+  group.bench_function("typed", |b| b.iter(|| runtime.block_on(fixed.async_run(black_box(10)))));
+  group.finish();
+}
+
+criterion_group!(benches, sync_chain, sync_fallback, async_chain);
+criterion_main!(benches);
@@ -1,17 +1,67 @@
-use std::{future::Future, ops::{BitAnd, BitOr, BitXor}, pin::Pin, sync::Arc};
+use std::{future::Future, ops::{BitAnd, BitOr, BitXor}, pin::Pin, sync::{Arc, OnceLock}, time::Instant};
 use async_trait::async_trait;
+use tokio::runtime::{Builder, Handle, Runtime};
+use futures::{future::{select_ok, try_join, try_join_all}, stream::{self, BoxStream, StreamExt, TryStreamExt}};
+use tracing::Instrument;
 
-use crate::{Pipeline, RawPipeline};
+use crate::{graph::PipelineNode, metrics::PipelineMetrics, Pipeline, RawPipeline};
 
 #[async_trait]
 pub trait RawAsyncPipeline<VT, RT, ET> {
   async fn async_run(&self,value: VT) -> Result<RT, ET>;
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("async_pipeline")
+  }
 }
 
 #[async_trait]
 impl<VT: Send + 'static,RT: Send + 'static,ET: Send + 'static> RawAsyncPipeline<VT,RT,ET> for Pipeline<VT,RT,ET> {
   async fn async_run(&self,value: VT) -> Result<RT, ET> {
-    self.run(value)
+    if self.is_blocking() {
+      let pipeline = self.clone();
+      run_blocking(move || pipeline.run(value)).await
+    } else {
+      self.run(value)
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    RawPipeline::node(self)
+  }
+}
+
+pub(crate) async fn run_blocking<T: Send + 'static, F: FnOnce() -> T + Send + 'static>(f: F) -> T {
+  let Ok(handle) = Handle::try_current() else {
+    return f();
+  };
+  match handle.spawn_blocking(f).await {
+    Ok(result) => result,
+    Err(e) => std::panic::resume_unwind(e.into_panic()),
+  }
+}
+
+struct BlockingAsyncPipeline<VT, RT, ET> {
+  inner: Pipeline<VT, RT, ET>
+}
+
+#[async_trait]
+impl<VT: Send + 'static,RT: Send + 'static,ET: Send + 'static> RawAsyncPipeline<VT,RT,ET> for BlockingAsyncPipeline<VT,RT,ET> {
+  async fn async_run(&self,value: VT) -> Result<RT, ET> {
+    let inner = self.inner.clone();
+    run_blocking(move || inner.run(value)).await
+  }
+
+  fn node(&self) -> PipelineNode {
+    RawPipeline::node(&self.inner).with_metadata("blocking", "true")
+  }
+}
+
+impl<VT: Send + 'static,RT: Send + 'static,ET: Send + 'static> Pipeline<VT,RT,ET> {
+  pub fn blocking(self) -> AsyncPipeline<VT,RT,ET> {
+    AsyncPipeline::new(BlockingAsyncPipeline {
+      inner: self
+    })
   }
 }
 
@@ -27,6 +77,28 @@ impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> AsyncPipeline<V
   }
 }
 
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> AsyncPipeline<VT,RT,ET> {
+  pub fn named(self, name: &str) -> AsyncPipeline<VT,RT,ET> {
+    AsyncPipeline::new(NamedAsyncPipeline {
+      name: name.to_string(),
+      metrics: PipelineMetrics::new(name),
+      inner: self
+    })
+  }
+
+  pub fn node(&self) -> PipelineNode {
+    self.raw.node()
+  }
+
+  pub fn meta(self, key: &str, value: &str) -> AsyncPipeline<VT,RT,ET> {
+    AsyncPipeline::new(MetaAsyncPipeline {
+      key: key.to_string(),
+      value: value.to_string(),
+      inner: self
+    })
+  }
+}
+
 impl<VT,RT,ET> Clone for AsyncPipeline<VT,RT,ET> {
   fn clone(&self) -> Self {
     Self { raw: self.raw.clone() }
@@ -38,6 +110,10 @@ impl<VT: Send + 'static,RT: Send + 'static,ET: Send + 'static> RawAsyncPipeline<
   async fn async_run(&self,value: VT) -> Result<RT, ET> {
     self.raw.async_run(value).await
   }
+
+  fn node(&self) -> PipelineNode {
+    self.raw.node()
+  }
 }
 
 impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> BitAnd<AsyncPipeline<VT,RT,ET>> for Result<VT,ET> {
@@ -53,6 +129,44 @@ impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> BitAnd<AsyncPip
     }
 }
 
+struct NamedAsyncPipeline<VT, RT, ET> {
+  name: String,
+  metrics: PipelineMetrics,
+  inner: AsyncPipeline<VT, RT, ET>
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<VT,RT,ET> for NamedAsyncPipeline<VT,RT,ET> {
+  async fn async_run(&self,value: VT) -> Result<RT, ET> {
+    let span = tracing::info_span!("pipeline", name = %self.name);
+    let start = Instant::now();
+    let result = self.inner.async_run(value).instrument(span).await;
+    self.metrics.record(start, result.is_ok());
+    result
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.inner.node().with_name(&self.name)
+  }
+}
+
+struct MetaAsyncPipeline<VT, RT, ET> {
+  key: String,
+  value: String,
+  inner: AsyncPipeline<VT, RT, ET>
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<VT,RT,ET> for MetaAsyncPipeline<VT,RT,ET> {
+  async fn async_run(&self,value: VT) -> Result<RT, ET> {
+    self.inner.async_run(value).await
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.inner.node().with_metadata(&self.key, &self.value)
+  }
+}
+
 struct AndAsyncPipeline<VT, MT, RT, ET> {
   lhs: AsyncPipeline<VT, MT, ET>,
   rhs: AsyncPipeline<MT, RT, ET>
@@ -66,6 +180,10 @@ impl<VT: Send + 'static, MT: Send + 'static, RT: Send + 'static, ET: Send + 'sta
       Err(e) => Err(e),
     }  
   }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("and", vec![self.lhs.node(), self.rhs.node()])
+  }
 }
 
 impl<VT: Send + 'static, MT:Send + 'static, RT: Send + 'static, ET: Send + 'static> BitAnd<AsyncPipeline<MT,RT,ET>> for AsyncPipeline<VT,MT,ET> {
@@ -114,6 +232,10 @@ impl<VT: Clone + Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyn
       Err(_) => self.rhs.async_run(value.clone()).await,
     }  
   }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("or", vec![self.lhs.node(), self.rhs.node()])
+  }
 }
 
 impl<VT: Clone + Send + 'static, RT: Send + 'static, ET: Send + 'static> BitOr<AsyncPipeline<VT,RT,ET>> for AsyncPipeline<VT,RT,ET> {
@@ -170,13 +292,197 @@ impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> BitAnd<Pipeline
     fn bitand(self, rhs: Pipeline<VT,RT,ET>) -> Self::Output {
       Box::pin(async move {
         match self.await {
-            Ok(v) => rhs.run(v),
+            Ok(v) => rhs.async_run(v).await,
             Err(e) => Err(e),
         }
       })
     }
 }
 
+struct JoinAsyncPipeline<VT, LT, RT, ET> {
+  lhs: AsyncPipeline<VT, LT, ET>,
+  rhs: AsyncPipeline<VT, RT, ET>
+}
+
+#[async_trait]
+impl<VT: Clone + Send + 'static, LT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<VT,(LT,RT),ET> for JoinAsyncPipeline<VT,LT,RT,ET> {
+  async fn async_run(&self,value: VT) -> Result<(LT,RT), ET> {
+    try_join(self.lhs.async_run(value.clone()), self.rhs.async_run(value)).await
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::nested("join", vec![self.lhs.node(), self.rhs.node()])
+  }
+}
+
+pub fn join<VT: Clone + Send + 'static, LT: Send + 'static, RT: Send + 'static, ET: Send + 'static>(lhs: AsyncPipeline<VT,LT,ET>, rhs: AsyncPipeline<VT,RT,ET>) -> AsyncPipeline<VT,(LT,RT),ET> {
+  AsyncPipeline::new(JoinAsyncPipeline {
+    lhs,
+    rhs
+  })
+}
+
+struct JoinAllAsyncPipeline<VT, RT, ET> {
+  pipelines: Vec<AsyncPipeline<VT, RT, ET>>
+}
+
+#[async_trait]
+impl<VT: Clone + Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<VT,Vec<RT>,ET> for JoinAllAsyncPipeline<VT,RT,ET> {
+  async fn async_run(&self,value: VT) -> Result<Vec<RT>, ET> {
+    try_join_all(self.pipelines.iter().map(|p| p.async_run(value.clone()))).await
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::nested("join", self.pipelines.iter().map(|p| p.node()).collect())
+  }
+}
+
+pub fn join_all<VT: Clone + Send + 'static, RT: Send + 'static, ET: Send + 'static>(pipelines: Vec<AsyncPipeline<VT,RT,ET>>) -> AsyncPipeline<VT,Vec<RT>,ET> {
+  AsyncPipeline::new(JoinAllAsyncPipeline {
+    pipelines
+  })
+}
+
+struct RaceAsyncPipeline<VT, RT, ET> {
+  lhs: AsyncPipeline<VT, RT, ET>,
+  rhs: AsyncPipeline<VT, RT, ET>
+}
+
+#[async_trait]
+impl<VT: Clone + Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<VT,RT,ET> for RaceAsyncPipeline<VT,RT,ET> {
+  async fn async_run(&self,value: VT) -> Result<RT, ET> {
+    select_ok([self.lhs.async_run(value.clone()), self.rhs.async_run(value)]).await.map(|(v, _)| v)
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("race", vec![self.lhs.node(), self.rhs.node()])
+  }
+}
+
+pub fn race<VT: Clone + Send + 'static, RT: Send + 'static, ET: Send + 'static>(lhs: AsyncPipeline<VT,RT,ET>, rhs: AsyncPipeline<VT,RT,ET>) -> AsyncPipeline<VT,RT,ET> {
+  AsyncPipeline::new(RaceAsyncPipeline {
+    lhs,
+    rhs
+  })
+}
+
+struct EachAsyncPipeline<VT, RT, ET> {
+  inner: AsyncPipeline<VT, RT, ET>,
+  limit: usize
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<Vec<VT>,Vec<RT>,ET> for EachAsyncPipeline<VT,RT,ET> {
+  async fn async_run(&self,values: Vec<VT>) -> Result<Vec<RT>, ET> {
+    stream::iter(values).map(|v| self.inner.async_run(v)).buffered(self.limit).try_collect().await
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::nested("each", vec![self.inner.node()]).with_metadata("each.limit", &self.limit.to_string())
+  }
+}
+
+pub fn async_each<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static>(p: AsyncPipeline<VT,RT,ET>, limit: usize) -> AsyncPipeline<Vec<VT>,Vec<RT>,ET> {
+  AsyncPipeline::new(EachAsyncPipeline {
+    inner: p,
+    limit: limit.max(1)
+  })
+}
+
+struct EachCollectAsyncPipeline<VT, RT, ET> {
+  inner: AsyncPipeline<VT, RT, ET>,
+  limit: usize
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<Vec<VT>,Vec<RT>,Vec<ET>> for EachCollectAsyncPipeline<VT,RT,ET> {
+  async fn async_run(&self,values: Vec<VT>) -> Result<Vec<RT>, Vec<ET>> {
+    let results: Vec<Result<RT, ET>> = stream::iter(values).map(|v| self.inner.async_run(v)).buffered(self.limit).collect().await;
+    let (oks, errors): (Vec<_>, Vec<_>) = results.into_iter().partition(|r| r.is_ok());
+    if errors.is_empty() {
+      Ok(oks.into_iter().filter_map(|r| r.ok()).collect())
+    } else {
+      Err(errors.into_iter().filter_map(|r| r.err()).collect())
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::nested("each_collect", vec![self.inner.node()]).with_metadata("each.limit", &self.limit.to_string())
+  }
+}
+
+pub fn async_each_collect<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static>(p: AsyncPipeline<VT,RT,ET>, limit: usize) -> AsyncPipeline<Vec<VT>,Vec<RT>,Vec<ET>> {
+  AsyncPipeline::new(EachCollectAsyncPipeline {
+    inner: p,
+    limit: limit.max(1)
+  })
+}
+
+struct StreamEachAsyncPipeline<VT, RT, ET> {
+  inner: AsyncPipeline<VT, RT, ET>,
+  limit: usize
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<BoxStream<'static, VT>,Vec<RT>,ET> for StreamEachAsyncPipeline<VT,RT,ET> {
+  async fn async_run(&self,values: BoxStream<'static, VT>) -> Result<Vec<RT>, ET> {
+    values.map(|v| self.inner.async_run(v)).buffered(self.limit).try_collect().await
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::nested("stream_each", vec![self.inner.node()]).with_metadata("each.limit", &self.limit.to_string())
+  }
+}
+
+pub fn stream_each<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static>(p: AsyncPipeline<VT,RT,ET>, limit: usize) -> AsyncPipeline<BoxStream<'static, VT>,Vec<RT>,ET> {
+  AsyncPipeline::new(StreamEachAsyncPipeline {
+    inner: p,
+    limit: limit.max(1)
+  })
+}
+
+fn runtime() -> &'static Runtime {
+  static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+  RUNTIME.get_or_init(|| Builder::new_current_thread().enable_all().build().expect("failed to build lopin runtime"))
+}
+
+struct BlockOnPipeline<VT, RT, ET> {
+  inner: AsyncPipeline<VT, RT, ET>,
+  handle: Option<Handle>
+}
+
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawPipeline<VT,RT,ET> for BlockOnPipeline<VT,RT,ET> {
+  fn run(&self,value: VT) -> Result<RT, ET> {
+    let future = self.inner.async_run(value);
+    match &self.handle {
+      Some(handle) => handle.block_on(future),
+      None => runtime().block_on(future),
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.inner.node().with_metadata("block_on", "true")
+  }
+
+  fn is_blocking(&self) -> bool {
+    true
+  }
+}
+
+pub fn block_on<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static>(p: AsyncPipeline<VT,RT,ET>) -> Pipeline<VT,RT,ET> {
+  Pipeline::new(BlockOnPipeline {
+    inner: p,
+    handle: None
+  })
+}
+
+pub fn block_on_with<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static>(p: AsyncPipeline<VT,RT,ET>, handle: Handle) -> Pipeline<VT,RT,ET> {
+  Pipeline::new(BlockOnPipeline {
+    inner: p,
+    handle: Some(handle)
+  })
+}
+
 struct SimpleAsyncPipeline<VT, RT, ET, FT : Future<Output = Result<RT,ET>> + Send> {
   raw: Arc<dyn Fn(VT) -> FT + Sync + Send>
 }
@@ -204,6 +510,10 @@ impl<CT : Clone + Sync,VT: Send, RT: Send, ET: Send, FT : Future<Output = Result
     async fn async_run(&self,value: VT) -> Result<RT, ET> {
       (self.raw)(self.context.clone(), value).await
     }
+
+    fn node(&self) -> PipelineNode {
+      PipelineNode::leaf("async_context")
+    }
 }
 
 pub fn async_context<CT : Clone + Sync + Send + 'static,VT: Send + 'static, RT: Send + 'static, ET: Send + 'static, FT : Future<Output = Result<RT,ET>> + Send + 'static, F: Fn(CT, VT) -> FT + Sync + Send + 'static>(c : CT, f : F) -> AsyncPipeline<VT,RT,ET> {
@@ -227,6 +537,10 @@ impl<VT: Send + Sync,ET : Sync + Clone> RawAsyncPipeline<VT,VT,ET> for FilterAsy
         Err(self.error.clone())
       }
     }
+
+    fn node(&self) -> PipelineNode {
+      PipelineNode::leaf("async_filter")
+    }
 }
 
 pub fn async_filter<T: Send + Sync + 'static, ET: Clone + Sync + Send + 'static, F: Fn(&T) -> Pin<Box<dyn Future<Output = bool> + Send + 'static>> + Sync + Send + 'static>(f: F, error: ET) -> AsyncPipeline<T, T, ET> {
@@ -0,0 +1,560 @@
+use std::{ops::{BitAnd, BitOr}, sync::Arc};
+
+use async_trait::async_trait;
+
+use crate::{async_core::run_blocking, graph::PipelineNode, AsyncPipeline, Pipeline, RawAsyncPipeline, RawPipeline};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BranchError<VT, ET> {
+  Rejected(VT, ET),
+  Failed(ET)
+}
+
+impl<VT, ET> BranchError<VT, ET> {
+  pub fn into_error(self) -> ET {
+    match self {
+      BranchError::Rejected(_, e) => e,
+      BranchError::Failed(e) => e,
+    }
+  }
+}
+
+type BranchFn<VT, RT, ET> = Arc<dyn Fn(VT) -> Result<RT, BranchError<VT, ET>> + Sync + Send + 'static>;
+type PreferFn<ET> = Arc<dyn Fn(&ET, &ET) -> bool + Sync + Send + 'static>;
+
+fn prefer_rhs<ET>() -> PreferFn<ET> {
+  Arc::new(|_: &ET, _: &ET| false)
+}
+
+pub trait RawBranch<VT, RT, ET> {
+  fn run(&self, value: VT) -> Result<RT, BranchError<VT, ET>>;
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("branch")
+  }
+
+  fn is_blocking(&self) -> bool {
+    false
+  }
+
+  // undo what this branch wrote into an accepted value when a later guard rejects it
+  fn restore(&self, value: VT) -> VT {
+    value
+  }
+}
+
+pub struct Branch<VT, RT, ET> {
+  raw: Arc<dyn RawBranch<VT, RT, ET> + Sync + Send + 'static>
+}
+
+impl<VT, RT, ET> Branch<VT, RT, ET> {
+  pub fn new<BranchT: RawBranch<VT, RT, ET> + Sync + Send + 'static>(raw: BranchT) -> Branch<VT, RT, ET> {
+    Branch {
+      raw: Arc::new(raw)
+    }
+  }
+
+  pub fn node(&self) -> PipelineNode {
+    self.raw.node()
+  }
+
+  pub fn is_blocking(&self) -> bool {
+    self.raw.is_blocking()
+  }
+}
+
+impl<VT: 'static, RT: 'static, ET: 'static> Branch<VT, RT, ET> {
+  pub fn meta(self, key: &str, value: &str) -> Branch<VT, RT, ET> {
+    Branch::new(MetaBranch {
+      key: key.to_string(),
+      value: value.to_string(),
+      inner: self
+    })
+  }
+
+  pub fn pipeline(self) -> Pipeline<VT, RT, ET> {
+    Pipeline::new(BranchPipeline {
+      inner: self
+    })
+  }
+
+  pub fn or_with<F: Fn(&ET, &ET) -> bool + Sync + Send + 'static>(self, rhs: Branch<VT, RT, ET>, keep_lhs: F) -> Branch<VT, RT, ET> {
+    Branch::new(OrBranch {
+      lhs: self,
+      rhs,
+      keep_lhs: Arc::new(keep_lhs)
+    })
+  }
+}
+
+impl<VT, RT, ET> Clone for Branch<VT, RT, ET> {
+  fn clone(&self) -> Self {
+    Self { raw: self.raw.clone() }
+  }
+}
+
+impl<VT, RT, ET> RawBranch<VT, RT, ET> for Branch<VT, RT, ET> {
+  fn run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    self.raw.run(value)
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.raw.node()
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.raw.is_blocking()
+  }
+
+  fn restore(&self, value: VT) -> VT {
+    self.raw.restore(value)
+  }
+}
+
+struct SimpleBranch<VT, RT, ET> {
+  raw: BranchFn<VT, RT, ET>
+}
+
+impl<VT, RT, ET> RawBranch<VT, RT, ET> for SimpleBranch<VT, RT, ET> {
+  fn run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    (self.raw)(value)
+  }
+}
+
+pub fn branch<VT: 'static, RT: 'static, ET: 'static, F: Fn(VT) -> Result<RT, BranchError<VT, ET>> + Sync + Send + 'static>(f: F) -> Branch<VT, RT, ET> {
+  Branch::new(SimpleBranch {
+    raw: Arc::new(f)
+  })
+}
+
+struct GuardBranch<VT, ET> {
+  raw: Arc<dyn Fn(&VT) -> bool + Sync + Send + 'static>,
+  error: ET
+}
+
+impl<VT, ET: Clone> RawBranch<VT, VT, ET> for GuardBranch<VT, ET> {
+  fn run(&self, value: VT) -> Result<VT, BranchError<VT, ET>> {
+    if (self.raw)(&value) {
+      Ok(value)
+    } else {
+      Err(BranchError::Rejected(value, self.error.clone()))
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("guard")
+  }
+}
+
+pub fn guard<T: 'static, ET: Clone + Sync + Send + 'static, F: Fn(&T) -> bool + Sync + Send + 'static>(f: F, error: ET) -> Branch<T, T, ET> {
+  Branch::new(GuardBranch {
+    raw: Arc::new(f),
+    error
+  })
+}
+
+struct MetaBranch<VT, RT, ET> {
+  key: String,
+  value: String,
+  inner: Branch<VT, RT, ET>
+}
+
+impl<VT, RT, ET> RawBranch<VT, RT, ET> for MetaBranch<VT, RT, ET> {
+  fn run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    self.inner.run(value)
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.inner.node().with_metadata(&self.key, &self.value)
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.inner.is_blocking()
+  }
+
+  fn restore(&self, value: VT) -> VT {
+    self.inner.restore(value)
+  }
+}
+
+struct BranchPipeline<VT, RT, ET> {
+  inner: Branch<VT, RT, ET>
+}
+
+impl<VT, RT, ET> RawPipeline<VT, RT, ET> for BranchPipeline<VT, RT, ET> {
+  fn run(&self, value: VT) -> Result<RT, ET> {
+    self.inner.run(value).map_err(BranchError::into_error)
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.inner.node()
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.inner.is_blocking()
+  }
+}
+
+struct AndBranch<VT, MT, RT, ET> {
+  lhs: Branch<VT, MT, ET>,
+  rhs: Pipeline<MT, RT, ET>
+}
+
+impl<VT, MT, RT, ET> RawBranch<VT, RT, ET> for AndBranch<VT, MT, RT, ET> {
+  fn run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    let v = self.lhs.run(value)?;
+    self.rhs.run(v).map_err(BranchError::Failed)
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("and", vec![self.lhs.node(), self.rhs.node()])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.lhs.is_blocking() || self.rhs.is_blocking()
+  }
+
+  fn restore(&self, value: VT) -> VT {
+    self.lhs.restore(value)
+  }
+}
+
+impl<VT: 'static, MT: 'static, RT: 'static, ET: 'static> BitAnd<Pipeline<MT, RT, ET>> for Branch<VT, MT, ET> {
+    type Output = Branch<VT, RT, ET>;
+
+    fn bitand(self, rhs: Pipeline<MT, RT, ET>) -> Self::Output {
+      Branch::new(AndBranch {
+        lhs: self,
+        rhs
+      })
+    }
+}
+
+struct GuardedBranch<VT, RT, ET> {
+  lhs: Branch<VT, VT, ET>,
+  rhs: Branch<VT, RT, ET>
+}
+
+impl<VT, RT, ET> RawBranch<VT, RT, ET> for GuardedBranch<VT, RT, ET> {
+  fn run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    let v = self.lhs.run(value)?;
+    match self.rhs.run(v) {
+      Err(BranchError::Rejected(v, e)) => Err(BranchError::Rejected(self.lhs.restore(v), e)),
+      result => result,
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("and", vec![self.lhs.node(), self.rhs.node()])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.lhs.is_blocking() || self.rhs.is_blocking()
+  }
+
+  fn restore(&self, value: VT) -> VT {
+    self.lhs.restore(self.rhs.restore(value))
+  }
+}
+
+impl<VT: 'static, RT: 'static, ET: 'static> BitAnd<Branch<VT, RT, ET>> for Branch<VT, VT, ET> {
+    type Output = Branch<VT, RT, ET>;
+
+    fn bitand(self, rhs: Branch<VT, RT, ET>) -> Self::Output {
+      Branch::new(GuardedBranch {
+        lhs: self,
+        rhs
+      })
+    }
+}
+
+struct OrBranch<VT, RT, ET> {
+  lhs: Branch<VT, RT, ET>,
+  rhs: Branch<VT, RT, ET>,
+  keep_lhs: PreferFn<ET>
+}
+
+impl<VT, RT, ET> RawBranch<VT, RT, ET> for OrBranch<VT, RT, ET> {
+  fn run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    match self.lhs.run(value) {
+      Err(BranchError::Rejected(v, lhs)) => match self.rhs.run(v) {
+        Err(BranchError::Rejected(v, rhs)) if (self.keep_lhs)(&lhs, &rhs) => Err(BranchError::Rejected(v, lhs)),
+        result => result,
+      },
+      result => result,
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("or", vec![self.lhs.node(), self.rhs.node()])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.lhs.is_blocking() || self.rhs.is_blocking()
+  }
+
+  fn restore(&self, value: VT) -> VT {
+    self.rhs.restore(self.lhs.restore(value))
+  }
+}
+
+impl<VT: 'static, RT: 'static, ET: 'static> BitOr<Branch<VT, RT, ET>> for Branch<VT, RT, ET> {
+    type Output = Branch<VT, RT, ET>;
+
+    fn bitor(self, rhs: Branch<VT, RT, ET>) -> Self::Output {
+      Branch::new(OrBranch {
+        lhs: self,
+        rhs,
+        keep_lhs: prefer_rhs()
+      })
+    }
+}
+
+struct FallbackPipeline<VT, RT, ET> {
+  lhs: Branch<VT, RT, ET>,
+  rhs: Pipeline<VT, RT, ET>
+}
+
+impl<VT, RT, ET> RawPipeline<VT, RT, ET> for FallbackPipeline<VT, RT, ET> {
+  fn run(&self, value: VT) -> Result<RT, ET> {
+    match self.lhs.run(value) {
+      Ok(v) => Ok(v),
+      Err(BranchError::Rejected(v, _)) => self.rhs.run(v),
+      Err(BranchError::Failed(e)) => Err(e),
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("or", vec![self.lhs.node(), self.rhs.node()])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.lhs.is_blocking() || self.rhs.is_blocking()
+  }
+}
+
+impl<VT: 'static, RT: 'static, ET: 'static> BitOr<Pipeline<VT, RT, ET>> for Branch<VT, RT, ET> {
+    type Output = Pipeline<VT, RT, ET>;
+
+    fn bitor(self, rhs: Pipeline<VT, RT, ET>) -> Self::Output {
+      Pipeline::new(FallbackPipeline {
+        lhs: self,
+        rhs
+      })
+    }
+}
+
+#[async_trait]
+pub trait RawAsyncBranch<VT, RT, ET> {
+  async fn async_run(&self, value: VT) -> Result<RT, BranchError<VT, ET>>;
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("async_branch")
+  }
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncBranch<VT, RT, ET> for Branch<VT, RT, ET> {
+  async fn async_run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    if self.is_blocking() {
+      let branch = self.clone();
+      run_blocking(move || branch.run(value)).await
+    } else {
+      self.run(value)
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    RawBranch::node(self)
+  }
+}
+
+pub struct AsyncBranch<VT, RT, ET> {
+  raw: Arc<dyn RawAsyncBranch<VT, RT, ET> + Sync + Send + 'static>
+}
+
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> AsyncBranch<VT, RT, ET> {
+  pub fn new<BranchT: RawAsyncBranch<VT, RT, ET> + Sync + Send + 'static>(raw: BranchT) -> AsyncBranch<VT, RT, ET> {
+    AsyncBranch {
+      raw: Arc::new(raw)
+    }
+  }
+
+  pub fn node(&self) -> PipelineNode {
+    self.raw.node()
+  }
+
+  pub fn async_pipeline(self) -> AsyncPipeline<VT, RT, ET> {
+    AsyncPipeline::new(AsyncBranchPipeline {
+      inner: self
+    })
+  }
+
+  pub fn or_with<F: Fn(&ET, &ET) -> bool + Sync + Send + 'static>(self, rhs: AsyncBranch<VT, RT, ET>, keep_lhs: F) -> AsyncBranch<VT, RT, ET> {
+    AsyncBranch::new(OrAsyncBranch {
+      lhs: self,
+      rhs,
+      keep_lhs: Arc::new(keep_lhs)
+    })
+  }
+}
+
+impl<VT, RT, ET> Clone for AsyncBranch<VT, RT, ET> {
+  fn clone(&self) -> Self {
+    Self { raw: self.raw.clone() }
+  }
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncBranch<VT, RT, ET> for AsyncBranch<VT, RT, ET> {
+  async fn async_run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    self.raw.async_run(value).await
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.raw.node()
+  }
+}
+
+struct AsyncBranchPipeline<VT, RT, ET> {
+  inner: AsyncBranch<VT, RT, ET>
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<VT, RT, ET> for AsyncBranchPipeline<VT, RT, ET> {
+  async fn async_run(&self, value: VT) -> Result<RT, ET> {
+    self.inner.async_run(value).await.map_err(BranchError::into_error)
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.inner.node()
+  }
+}
+
+struct AndAsyncBranch<VT, MT, RT, ET> {
+  lhs: AsyncBranch<VT, MT, ET>,
+  rhs: AsyncPipeline<MT, RT, ET>
+}
+
+#[async_trait]
+impl<VT: Send + 'static, MT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncBranch<VT, RT, ET> for AndAsyncBranch<VT, MT, RT, ET> {
+  async fn async_run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    let v = self.lhs.async_run(value).await?;
+    self.rhs.async_run(v).await.map_err(BranchError::Failed)
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("and", vec![self.lhs.node(), self.rhs.node()])
+  }
+}
+
+impl<VT: Send + 'static, MT: Send + 'static, RT: Send + 'static, ET: Send + 'static> BitAnd<AsyncPipeline<MT, RT, ET>> for AsyncBranch<VT, MT, ET> {
+    type Output = AsyncBranch<VT, RT, ET>;
+
+    fn bitand(self, rhs: AsyncPipeline<MT, RT, ET>) -> Self::Output {
+      AsyncBranch::new(AndAsyncBranch {
+        lhs: self,
+        rhs
+      })
+    }
+}
+
+impl<VT: Send + 'static, MT: Send + 'static, RT: Send + 'static, ET: Send + 'static> BitAnd<AsyncPipeline<MT, RT, ET>> for Branch<VT, MT, ET> {
+    type Output = AsyncBranch<VT, RT, ET>;
+
+    fn bitand(self, rhs: AsyncPipeline<MT, RT, ET>) -> Self::Output {
+      AsyncBranch::new(AndAsyncBranch {
+        lhs: AsyncBranch::new(self),
+        rhs
+      })
+    }
+}
+
+struct OrAsyncBranch<VT, RT, ET> {
+  lhs: AsyncBranch<VT, RT, ET>,
+  rhs: AsyncBranch<VT, RT, ET>,
+  keep_lhs: PreferFn<ET>
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncBranch<VT, RT, ET> for OrAsyncBranch<VT, RT, ET> {
+  async fn async_run(&self, value: VT) -> Result<RT, BranchError<VT, ET>> {
+    match self.lhs.async_run(value).await {
+      Err(BranchError::Rejected(v, lhs)) => match self.rhs.async_run(v).await {
+        Err(BranchError::Rejected(v, rhs)) if (self.keep_lhs)(&lhs, &rhs) => Err(BranchError::Rejected(v, lhs)),
+        result => result,
+      },
+      result => result,
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("or", vec![self.lhs.node(), self.rhs.node()])
+  }
+}
+
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> BitOr<AsyncBranch<VT, RT, ET>> for AsyncBranch<VT, RT, ET> {
+    type Output = AsyncBranch<VT, RT, ET>;
+
+    fn bitor(self, rhs: AsyncBranch<VT, RT, ET>) -> Self::Output {
+      AsyncBranch::new(OrAsyncBranch {
+        lhs: self,
+        rhs,
+        keep_lhs: prefer_rhs()
+      })
+    }
+}
+
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> BitOr<Branch<VT, RT, ET>> for AsyncBranch<VT, RT, ET> {
+    type Output = AsyncBranch<VT, RT, ET>;
+
+    fn bitor(self, rhs: Branch<VT, RT, ET>) -> Self::Output {
+      AsyncBranch::new(OrAsyncBranch {
+        lhs: self,
+        rhs: AsyncBranch::new(rhs),
+        keep_lhs: prefer_rhs()
+      })
+    }
+}
+
+struct FallbackAsyncPipeline<VT, RT, ET> {
+  lhs: AsyncBranch<VT, RT, ET>,
+  rhs: AsyncPipeline<VT, RT, ET>
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncPipeline<VT, RT, ET> for FallbackAsyncPipeline<VT, RT, ET> {
+  async fn async_run(&self, value: VT) -> Result<RT, ET> {
+    match self.lhs.async_run(value).await {
+      Ok(v) => Ok(v),
+      Err(BranchError::Rejected(v, _)) => self.rhs.async_run(v).await,
+      Err(BranchError::Failed(e)) => Err(e),
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("or", vec![self.lhs.node(), self.rhs.node()])
+  }
+}
+
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> BitOr<AsyncPipeline<VT, RT, ET>> for AsyncBranch<VT, RT, ET> {
+    type Output = AsyncPipeline<VT, RT, ET>;
+
+    fn bitor(self, rhs: AsyncPipeline<VT, RT, ET>) -> Self::Output {
+      AsyncPipeline::new(FallbackAsyncPipeline {
+        lhs: self,
+        rhs
+      })
+    }
+}
+
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> BitOr<Pipeline<VT, RT, ET>> for AsyncBranch<VT, RT, ET> {
+    type Output = AsyncPipeline<VT, RT, ET>;
+
+    fn bitor(self, rhs: Pipeline<VT, RT, ET>) -> Self::Output {
+      AsyncPipeline::new(FallbackAsyncPipeline {
+        lhs: self,
+        rhs: AsyncPipeline::new(rhs)
+      })
+    }
+}
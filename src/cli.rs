@@ -0,0 +1,119 @@
+use std::{collections::HashMap, fmt::Display, io::{self, Write}, process};
+
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use tokio::io::{stderr, stdin, stdout, AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::{de::{bool_fields, from_multi_map}, framework, AsyncFramework, AsyncPipeline, Framework, Pipeline, RawAsyncFramework, RawAsyncPipeline, RawPipeline};
+
+pub async fn process_lines<E: Display + Send + 'static, R: AsyncBufRead + Unpin, W: AsyncWrite + Unpin, EW: AsyncWrite + Unpin>(pipeline: &AsyncPipeline<String, String, E>, input: R, output: &mut W, errors: &mut EW) -> io::Result<usize> {
+  let mut lines = input.lines();
+  let mut failures = 0;
+  while let Some(line) = lines.next_line().await? {
+    match pipeline.async_run(line).await {
+      Ok(result) => {
+        output.write_all(result.as_bytes()).await?;
+        output.write_all(b"\n").await?;
+        output.flush().await?;
+      },
+      Err(e) => {
+        failures += 1;
+        errors.write_all(format!("{}\n", e).as_bytes()).await?;
+        errors.flush().await?;
+      },
+    }
+  }
+  Ok(failures)
+}
+
+pub async fn run_lines<E: Display + Send + 'static, R: AsyncBufRead + Unpin, W: AsyncWrite + Unpin, EW: AsyncWrite + Unpin>(pipeline: &AsyncPipeline<String, String, E>, input: R, output: &mut W, errors: &mut EW) -> i32 {
+  match process_lines(pipeline, input, output, errors).await {
+    Ok(0) => 0,
+    Ok(_) => 1,
+    Err(e) => {
+      let _ = errors.write_all(format!("{}\n", e).as_bytes()).await;
+      let _ = errors.flush().await;
+      1
+    },
+  }
+}
+
+struct StdioLines;
+
+#[async_trait]
+impl<E: Display + Send + 'static> RawAsyncFramework<String, String, E> for StdioLines {
+  async fn run(&self, pipeline: AsyncPipeline<String, String, E>) {
+    let code = run_lines(&pipeline, BufReader::new(stdin()), &mut stdout(), &mut stderr()).await;
+    if code != 0 {
+      process::exit(code);
+    }
+  }
+}
+
+pub fn stdio_lines<E: Display + Send + 'static>() -> AsyncFramework<String, String, E> {
+  AsyncFramework::new(StdioLines)
+}
+
+pub fn parse_args<T: DeserializeOwned, I: IntoIterator<Item = String>>(args: I) -> Result<T, String> {
+  let flags = bool_fields::<T>();
+  let mut map: HashMap<String, Vec<String>> = HashMap::new();
+  let mut args = args.into_iter().peekable();
+  let mut positional = false;
+  while let Some(arg) = args.next() {
+    let option = arg.strip_prefix("--").filter(|name| !positional && !name.is_empty());
+    match option {
+      Some(option) => {
+        let (name, value) = match option.split_once('=') {
+          Some((name, value)) => (name.replace('-', "_"), value.to_string()),
+          None => {
+            let name = option.replace('-', "_");
+            if flags.contains(&name.as_str()) {
+              (name, "true".to_string())
+            } else {
+              match args.next_if(|next| !next.starts_with("--")) {
+                Some(value) => (name, value),
+                None => return Err(format!("missing value for --{option}")),
+              }
+            }
+          },
+        };
+        map.entry(name).or_default().push(value);
+      },
+      None if !positional && arg == "--" => positional = true,
+      None => map.entry("args".to_string()).or_default().push(arg),
+    }
+  }
+  from_multi_map(&map).map_err(|e| e.to_string())
+}
+
+pub fn run_args<T: DeserializeOwned, RT: Display, ET: Display, I: IntoIterator<Item = String>, W: Write, EW: Write>(pipeline: &Pipeline<T, RT, ET>, args: I, output: &mut W, errors: &mut EW) -> i32 {
+  let value = match parse_args(args) {
+    Ok(value) => value,
+    Err(e) => {
+      let _ = writeln!(errors, "invalid arguments: {}", e);
+      return 2;
+    },
+  };
+  match pipeline.run(value) {
+    Ok(result) => match writeln!(output, "{}", result) {
+      Ok(_) => 0,
+      Err(e) => {
+        let _ = writeln!(errors, "{}", e);
+        1
+      },
+    },
+    Err(e) => {
+      let _ = writeln!(errors, "{}", e);
+      1
+    },
+  }
+}
+
+pub fn args_framework<T: DeserializeOwned + 'static, RT: Display + 'static, ET: Display + 'static>() -> Framework<T, RT, ET> {
+  framework(|p| {
+    let code = run_args(&p, std::env::args().skip(1), &mut io::stdout(), &mut io::stderr());
+    if code != 0 {
+      process::exit(code);
+    }
+  })
+}
@@ -1,10 +1,532 @@
-use std::{io, process::Command};
+use std::{fmt::{self, Display}, future::Future, io::{self, Read, Write}, ops::BitAnd, path::PathBuf, process::{Child, Command, ExitStatus, Stdio}, thread, time::{Duration, Instant}};
 
-use crate::{pipeline, Pipeline};
+use async_trait::async_trait;
+use futures::future::{try_join4, try_join_all};
+use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, process::ChildStdout};
+
+use crate::{graph::PipelineNode, pipeline, AsyncPipeline, Pipeline, RawAsyncPipeline, RawPipeline};
 
 pub fn command(com: &str) -> Pipeline<(), (), io::Error> {
   let text = com.to_string();
   pipeline(move |_| {
     Command::new(&text).spawn().and_then(|_| Ok(()))
+  }).mark_blocking()
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommandInput {
+  pub args: Vec<String>,
+  pub env: Vec<(String, String)>,
+  pub dir: Option<PathBuf>,
+  pub stdin: Option<Vec<u8>>
+}
+
+impl CommandInput {
+  pub fn new() -> CommandInput {
+    CommandInput::default()
+  }
+
+  pub fn arg(mut self, arg: &str) -> CommandInput {
+    self.args.push(arg.to_string());
+    self
+  }
+
+  pub fn env(mut self, key: &str, value: &str) -> CommandInput {
+    self.env.push((key.to_string(), value.to_string()));
+    self
+  }
+
+  pub fn dir(self, dir: &str) -> CommandInput {
+    CommandInput {
+      dir: Some(PathBuf::from(dir)),
+      ..self
+    }
+  }
+
+  pub fn stdin(self, stdin: &[u8]) -> CommandInput {
+    CommandInput {
+      stdin: Some(stdin.to_vec()),
+      ..self
+    }
+  }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommandOutput {
+  pub status: Option<i32>,
+  pub stdout: Vec<u8>,
+  pub stderr: Vec<u8>
+}
+
+impl CommandOutput {
+  pub fn success(&self) -> bool {
+    self.status == Some(0)
+  }
+
+  pub fn stdout_string(&self) -> String {
+    String::from_utf8_lossy(&self.stdout).into_owned()
+  }
+
+  pub fn stderr_string(&self) -> String {
+    String::from_utf8_lossy(&self.stderr).into_owned()
+  }
+}
+
+#[derive(Debug)]
+pub enum CommandError {
+  Io(io::Error),
+  Exit(CommandOutput),
+  Timeout
+}
+
+impl Display for CommandError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      CommandError::Io(e) => write!(f, "{}", e),
+      CommandError::Exit(output) => match output.status {
+        Some(code) => write!(f, "command exited with status {}", code),
+        None => write!(f, "command terminated by signal"),
+      },
+      CommandError::Timeout => write!(f, "command timed out"),
+    }
+  }
+}
+
+impl std::error::Error for CommandError {}
+
+impl From<io::Error> for CommandError {
+  fn from(e: io::Error) -> Self {
+    CommandError::Io(e)
+  }
+}
+
+#[derive(Clone, Debug)]
+pub struct CommandSpec {
+  program: String,
+  args: Vec<String>,
+  env: Vec<(String, String)>,
+  dir: Option<PathBuf>,
+  timeout: Option<Duration>,
+  kill_on_drop: bool
+}
+
+impl CommandSpec {
+  pub fn new(program: &str) -> CommandSpec {
+    CommandSpec {
+      program: program.to_string(),
+      args: vec![],
+      env: vec![],
+      dir: None,
+      timeout: None,
+      kill_on_drop: true
+    }
+  }
+
+  pub fn arg(mut self, arg: &str) -> CommandSpec {
+    self.args.push(arg.to_string());
+    self
+  }
+
+  pub fn args(mut self, args: &[&str]) -> CommandSpec {
+    self.args.extend(args.iter().map(|a| a.to_string()));
+    self
+  }
+
+  pub fn env(mut self, key: &str, value: &str) -> CommandSpec {
+    self.env.push((key.to_string(), value.to_string()));
+    self
+  }
+
+  pub fn current_dir(self, dir: &str) -> CommandSpec {
+    CommandSpec {
+      dir: Some(PathBuf::from(dir)),
+      ..self
+    }
+  }
+
+  pub fn timeout(self, timeout: Duration) -> CommandSpec {
+    CommandSpec {
+      timeout: Some(timeout),
+      ..self
+    }
+  }
+
+  pub fn kill_on_drop(self, kill_on_drop: bool) -> CommandSpec {
+    CommandSpec {
+      kill_on_drop,
+      ..self
+    }
+  }
+
+  fn command(&self, input: &CommandInput) -> Command {
+    let mut command = Command::new(&self.program);
+    command.args(&self.args)
+      .args(&input.args)
+      .envs(self.env.iter().chain(input.env.iter()).map(|(k, v)| (k, v)))
+      .stdin(if input.stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+      .stdout(Stdio::piped())
+      .stderr(Stdio::piped());
+    if let Some(dir) = input.dir.as_ref().or(self.dir.as_ref()) {
+      command.current_dir(dir);
+    }
+    command
+  }
+
+  pub fn run(&self, input: CommandInput) -> Result<CommandOutput, CommandError> {
+    let mut child = ChildGuard {
+      child: self.command(&input).spawn()?,
+      kill_on_drop: self.kill_on_drop,
+      done: false
+    };
+    let stdin = child.child.stdin.take().zip(input.stdin).map(|(mut pipe, data)| thread::spawn(move || pipe.write_all(&data)));
+    let stdout = child.child.stdout.take().map(read_all);
+    let stderr = child.child.stderr.take().map(read_all);
+    let status = match self.timeout {
+      Some(timeout) => child.wait_timeout(timeout)?,
+      None => child.wait()?,
+    };
+    let Some(status) = status else {
+      return Err(CommandError::Timeout);
+    };
+    let output = CommandOutput {
+      status: status.code(),
+      stdout: stdout.map(join_reader).transpose()?.unwrap_or_default(),
+      stderr: stderr.map(join_reader).transpose()?.unwrap_or_default()
+    };
+    if let Some(stdin) = stdin {
+      match stdin.join() {
+        Ok(Err(e)) if e.kind() != io::ErrorKind::BrokenPipe => return Err(e.into()),
+        _ => (),
+      }
+    }
+    if status.success() {
+      Ok(output)
+    } else {
+      Err(CommandError::Exit(output))
+    }
+  }
+
+  pub fn pipeline(&self) -> Pipeline<CommandInput, CommandOutput, CommandError> {
+    Pipeline::new(CommandPipeline {
+      spec: self.clone()
+    })
+  }
+
+  async fn spawn_async<T, F: Future<Output = Result<T, CommandError>>, H: FnOnce(ChildStdout) -> F>(&self, input: CommandInput, handler: H) -> Result<(ExitStatus, T, Vec<u8>), CommandError> {
+    let mut command = tokio::process::Command::from(self.command(&input));
+    let mut child = command.kill_on_drop(self.kill_on_drop).spawn()?;
+    let stdin = child.stdin.take();
+    let stdout = child.stdout.take().ok_or_else(|| io::Error::other("stdout is not captured"))?;
+    let stderr = child.stderr.take();
+    let write_stdin = async {
+      if let Some((mut pipe, data)) = stdin.zip(input.stdin) {
+        match pipe.write_all(&data).await {
+          Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(CommandError::Io(e)),
+          _ => (),
+        }
+      }
+      Ok(())
+    };
+    let read_stderr = async {
+      let mut buf = vec![];
+      if let Some(mut stderr) = stderr {
+        stderr.read_to_end(&mut buf).await?;
+      }
+      Ok(buf)
+    };
+    let wait = async { child.wait().await.map_err(CommandError::Io) };
+    let running = try_join4(wait, handler(stdout), read_stderr, write_stdin);
+    let result = match self.timeout {
+      Some(timeout) => tokio::time::timeout(timeout, running).await.unwrap_or(Err(CommandError::Timeout)),
+      None => running.await,
+    };
+    match result {
+      Ok((status, value, stderr, _)) => Ok((status, value, stderr)),
+      Err(e) => {
+        if self.kill_on_drop {
+          let _ = child.kill().await;
+        }
+        Err(e)
+      },
+    }
+  }
+
+  pub async fn async_run(&self, input: CommandInput) -> Result<CommandOutput, CommandError> {
+    let (status, stdout, stderr) = self.spawn_async(input, |mut stdout| async move {
+      let mut buf = vec![];
+      stdout.read_to_end(&mut buf).await?;
+      Ok(buf)
+    }).await?;
+    let output = CommandOutput {
+      status: status.code(),
+      stdout,
+      stderr
+    };
+    if status.success() {
+      Ok(output)
+    } else {
+      Err(CommandError::Exit(output))
+    }
+  }
+
+  pub fn async_pipeline(&self) -> AsyncPipeline<CommandInput, CommandOutput, CommandError> {
+    AsyncPipeline::new(AsyncCommandPipeline {
+      spec: self.clone()
+    })
+  }
+
+  pub fn async_lines<RT: Send + 'static>(&self, lines: AsyncPipeline<String, RT, CommandError>) -> AsyncPipeline<CommandInput, Vec<RT>, CommandError> {
+    AsyncPipeline::new(AsyncLinesPipeline {
+      spec: self.clone(),
+      lines
+    })
+  }
+}
+
+pub fn async_command(program: &str) -> AsyncPipeline<CommandInput, CommandOutput, CommandError> {
+  CommandSpec::new(program).async_pipeline()
+}
+
+pub fn async_pipe(specs: &[CommandSpec]) -> AsyncPipeline<CommandInput, CommandOutput, CommandError> {
+  AsyncPipeline::new(AsyncPipePipeline {
+    specs: specs.to_vec()
+  })
+}
+
+#[derive(Clone, Debug)]
+pub struct CommandPipe {
+  specs: Vec<CommandSpec>
+}
+
+impl CommandPipe {
+  pub fn async_pipeline(&self) -> AsyncPipeline<CommandInput, CommandOutput, CommandError> {
+    async_pipe(&self.specs)
+  }
+}
+
+impl BitAnd<CommandSpec> for CommandSpec {
+    type Output = CommandPipe;
+
+    fn bitand(self, rhs: CommandSpec) -> Self::Output {
+      CommandPipe {
+        specs: vec![self, rhs]
+      }
+    }
+}
+
+impl BitAnd<CommandSpec> for CommandPipe {
+    type Output = CommandPipe;
+
+    fn bitand(mut self, rhs: CommandSpec) -> Self::Output {
+      self.specs.push(rhs);
+      self
+    }
+}
+
+async fn run_pipe(specs: &[CommandSpec], input: CommandInput) -> Result<CommandOutput, CommandError> {
+  let mut children = vec![];
+  let mut previous: Option<Stdio> = None;
+  for (i, spec) in specs.iter().enumerate() {
+    let mut command = if i == 0 {
+      spec.command(&input)
+    } else {
+      spec.command(&CommandInput { args: vec![], stdin: None, ..input.clone() })
+    };
+    if let Some(stdin) = previous.take() {
+      command.stdin(stdin);
+    }
+    let mut child = tokio::process::Command::from(command).kill_on_drop(spec.kill_on_drop).spawn()?;
+    if i + 1 < specs.len() {
+      let stdout = child.stdout.take().ok_or_else(|| io::Error::other("stdout is not captured"))?;
+      previous = Some(stdout.try_into()?);
+    }
+    children.push(child);
+  }
+  let stdin = children.first_mut().and_then(|c| c.stdin.take());
+  let stdout = children.last_mut().and_then(|c| c.stdout.take());
+  let stderrs: Vec<_> = children.iter_mut().map(|c| c.stderr.take()).collect();
+  let write_stdin = async {
+    if let Some((mut pipe, data)) = stdin.zip(input.stdin.clone()) {
+      match pipe.write_all(&data).await {
+        Err(e) if e.kind() != io::ErrorKind::BrokenPipe => return Err(CommandError::Io(e)),
+        _ => (),
+      }
+    }
+    Ok(())
+  };
+  let read_stdout = async {
+    let mut buf = vec![];
+    if let Some(mut stdout) = stdout {
+      stdout.read_to_end(&mut buf).await?;
+    }
+    Ok(buf)
+  };
+  let read_stderr = try_join_all(stderrs.into_iter().map(|stderr| async move {
+    let mut buf = vec![];
+    if let Some(mut stderr) = stderr {
+      stderr.read_to_end(&mut buf).await?;
+    }
+    Ok::<_, CommandError>(buf)
+  }));
+  let wait = try_join_all(children.iter_mut().map(|c| async { c.wait().await.map_err(CommandError::Io) }));
+  let running = try_join4(wait, read_stdout, read_stderr, write_stdin);
+  let result = match specs.iter().filter_map(|s| s.timeout).min() {
+    Some(timeout) => tokio::time::timeout(timeout, running).await.unwrap_or(Err(CommandError::Timeout)),
+    None => running.await,
+  };
+  let (statuses, stdout, stderr, _) = match result {
+    Ok(result) => result,
+    Err(e) => {
+      for (child, spec) in children.iter_mut().zip(specs) {
+        if spec.kill_on_drop {
+          let _ = child.kill().await;
+        }
+      }
+      return Err(e);
+    },
+  };
+  let status = statuses.last();
+  let output = CommandOutput {
+    status: status.and_then(|s| s.code()),
+    stdout,
+    stderr: stderr.concat()
+  };
+  match status {
+    Some(s) if !s.success() => Err(CommandError::Exit(output)),
+    _ => Ok(output),
+  }
+}
+
+struct ChildGuard {
+  child: Child,
+  kill_on_drop: bool,
+  done: bool
+}
+
+impl ChildGuard {
+  fn wait(&mut self) -> io::Result<Option<ExitStatus>> {
+    let status = self.child.wait()?;
+    self.done = true;
+    Ok(Some(status))
+  }
+
+  fn wait_timeout(&mut self, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+      if let Some(status) = self.child.try_wait()? {
+        self.done = true;
+        break Ok(Some(status));
+      }
+      if Instant::now() >= deadline {
+        if self.kill_on_drop {
+          self.child.kill()?;
+          self.child.wait()?;
+        }
+        self.done = true;
+        break Ok(None);
+      }
+      thread::sleep(Duration::from_millis(5));
+    }
+  }
+}
+
+impl Drop for ChildGuard {
+  fn drop(&mut self) {
+    if self.kill_on_drop && !self.done {
+      let _ = self.child.kill();
+      let _ = self.child.wait();
+    }
+  }
+}
+
+fn read_all<R: Read + Send + 'static>(mut reader: R) -> thread::JoinHandle<io::Result<Vec<u8>>> {
+  thread::spawn(move || {
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf).map(|_| buf)
   })
-}
\ No newline at end of file
+}
+
+fn join_reader(handle: thread::JoinHandle<io::Result<Vec<u8>>>) -> io::Result<Vec<u8>> {
+  handle.join().unwrap_or_else(|_| Err(io::Error::other("output reader panicked")))
+}
+
+struct CommandPipeline {
+  spec: CommandSpec
+}
+
+impl RawPipeline<CommandInput, CommandOutput, CommandError> for CommandPipeline {
+  fn run(&self, input: CommandInput) -> Result<CommandOutput, CommandError> {
+    self.spec.run(input)
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("command").with_metadata("command.program", &self.spec.program)
+  }
+
+  fn is_blocking(&self) -> bool {
+    true
+  }
+}
+
+struct AsyncCommandPipeline {
+  spec: CommandSpec
+}
+
+#[async_trait]
+impl RawAsyncPipeline<CommandInput, CommandOutput, CommandError> for AsyncCommandPipeline {
+  async fn async_run(&self, input: CommandInput) -> Result<CommandOutput, CommandError> {
+    self.spec.async_run(input).await
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("async_command").with_metadata("command.program", &self.spec.program)
+  }
+}
+
+struct AsyncLinesPipeline<RT> {
+  spec: CommandSpec,
+  lines: AsyncPipeline<String, RT, CommandError>
+}
+
+#[async_trait]
+impl<RT: Send + 'static> RawAsyncPipeline<CommandInput, Vec<RT>, CommandError> for AsyncLinesPipeline<RT> {
+  async fn async_run(&self, input: CommandInput) -> Result<Vec<RT>, CommandError> {
+    let (status, results, stderr) = self.spec.spawn_async(input, |stdout| async move {
+      let mut lines = BufReader::new(stdout).lines();
+      let mut results = vec![];
+      while let Some(line) = lines.next_line().await? {
+        results.push(self.lines.async_run(line).await?);
+      }
+      Ok(results)
+    }).await?;
+    if status.success() {
+      Ok(results)
+    } else {
+      Err(CommandError::Exit(CommandOutput {
+        status: status.code(),
+        stdout: vec![],
+        stderr
+      }))
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("async_command", vec![self.lines.node()]).with_metadata("command.program", &self.spec.program)
+  }
+}
+
+struct AsyncPipePipeline {
+  specs: Vec<CommandSpec>
+}
+
+#[async_trait]
+impl RawAsyncPipeline<CommandInput, CommandOutput, CommandError> for AsyncPipePipeline {
+  async fn async_run(&self, input: CommandInput) -> Result<CommandOutput, CommandError> {
+    run_pipe(&self.specs, input).await
+  }
+
+  fn node(&self) -> PipelineNode {
+    let programs = self.specs.iter().map(|s| s.program.as_str()).collect::<Vec<&str>>().join(" | ");
+    PipelineNode::leaf("async_command").with_metadata("command.program", &programs)
+  }
+}
@@ -1,7 +1,17 @@
-use std::{ops::{BitAnd, BitOr, BitXor}, sync::Arc};
+use std::{ops::{BitAnd, BitOr, BitXor}, sync::Arc, time::Instant};
+
+use crate::{graph::PipelineNode, metrics::PipelineMetrics};
 
 pub trait RawPipeline<VT, RT, ET> {
   fn run(&self,value: VT) -> Result<RT, ET>;
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("pipeline")
+  }
+
+  fn is_blocking(&self) -> bool {
+    false
+  }
 }
 pub struct Pipeline<VT, RT, ET> {
   raw: Arc<dyn RawPipeline<VT, RT, ET> + Sync + Send + 'static>,
@@ -13,6 +23,42 @@ impl<VT,RT,ET> Pipeline<VT,RT,ET> {
       raw: Arc::new(raw)
     };
   }
+
+  pub fn node(&self) -> PipelineNode {
+    self.raw.node()
+  }
+
+  pub fn is_blocking(&self) -> bool {
+    self.raw.is_blocking()
+  }
+}
+
+impl<VT: 'static, RT: 'static, ET: 'static> Pipeline<VT,RT,ET> {
+  pub fn named(self, name: &str) -> Pipeline<VT,RT,ET> {
+    Pipeline::new(NamedPipeline {
+      name: name.to_string(),
+      metrics: PipelineMetrics::new(name),
+      inner: self
+    })
+  }
+
+  pub fn meta(self, key: &str, value: &str) -> Pipeline<VT,RT,ET> {
+    Pipeline::new(MetaPipeline {
+      key: key.to_string(),
+      value: value.to_string(),
+      inner: self
+    })
+  }
+
+  pub fn mark_blocking(self) -> Pipeline<VT,RT,ET> {
+    Pipeline::new(BlockingPipeline {
+      inner: self
+    })
+  }
+
+  pub(crate) fn blocking_if(self, blocking: bool) -> Pipeline<VT,RT,ET> {
+    if blocking { self.mark_blocking() } else { self }
+  }
 }
 
 impl<VT,RT,ET> Clone for Pipeline<VT,RT,ET> {
@@ -25,6 +71,14 @@ impl<VT,RT,ET> RawPipeline<VT,RT,ET> for Pipeline<VT,RT,ET> {
   fn run(&self,value: VT) -> Result<RT, ET> {
     self.raw.run(value)
   }
+
+  fn node(&self) -> PipelineNode {
+    self.raw.node()
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.raw.is_blocking()
+  }
 }
 
 impl<VT: 'static, RT: 'static, ET: 'static> BitAnd<Pipeline<VT,RT,ET>> for Result<VT,ET> {
@@ -38,6 +92,69 @@ impl<VT: 'static, RT: 'static, ET: 'static> BitAnd<Pipeline<VT,RT,ET>> for Resul
     }
 }
 
+struct NamedPipeline<VT, RT, ET> {
+  name: String,
+  metrics: PipelineMetrics,
+  inner: Pipeline<VT, RT, ET>
+}
+
+impl<VT, RT, ET> RawPipeline<VT,RT,ET> for NamedPipeline<VT,RT,ET> {
+  fn run(&self,value: VT) -> Result<RT, ET> {
+    let span = tracing::info_span!("pipeline", name = %self.name);
+    let _enter = span.enter();
+    let start = Instant::now();
+    let result = self.inner.run(value);
+    self.metrics.record(start, result.is_ok());
+    result
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.inner.node().with_name(&self.name)
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.inner.is_blocking()
+  }
+}
+
+struct MetaPipeline<VT, RT, ET> {
+  key: String,
+  value: String,
+  inner: Pipeline<VT, RT, ET>
+}
+
+impl<VT, RT, ET> RawPipeline<VT,RT,ET> for MetaPipeline<VT,RT,ET> {
+  fn run(&self,value: VT) -> Result<RT, ET> {
+    self.inner.run(value)
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.inner.node().with_metadata(&self.key, &self.value)
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.inner.is_blocking()
+  }
+}
+
+struct BlockingPipeline<VT, RT, ET> {
+  inner: Pipeline<VT, RT, ET>
+}
+
+impl<VT, RT, ET> RawPipeline<VT,RT,ET> for BlockingPipeline<VT,RT,ET> {
+  fn run(&self,value: VT) -> Result<RT, ET> {
+    self.inner.run(value)
+  }
+
+  fn node(&self) -> PipelineNode {
+    self.inner.node().with_metadata("blocking", "true")
+  }
+
+  fn is_blocking(&self) -> bool {
+    true
+  }
+}
+
 struct AndPipeline<VT, MT, RT, ET> {
   lhs: Pipeline<VT, MT, ET>,
   rhs: Pipeline<MT, RT, ET>
@@ -50,6 +167,14 @@ impl<VT: 'static, MT: 'static, RT: 'static, ET: 'static> RawPipeline<VT,RT,ET> f
       Err(e) => Err(e),
     }  
   }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("and", vec![self.lhs.node(), self.rhs.node()])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.lhs.is_blocking() || self.rhs.is_blocking()
+  }
 }
 
 impl<VT: 'static, MT:'static, RT: 'static, ET: 'static> BitAnd<Pipeline<MT,RT,ET>> for Pipeline<VT,MT,ET> {
@@ -75,6 +200,14 @@ impl<VT: Clone + 'static, RT: 'static, ET: 'static> RawPipeline<VT,RT,ET> for Or
       Err(_) => self.rhs.run(value.clone()),
     }  
   }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("or", vec![self.lhs.node(), self.rhs.node()])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.lhs.is_blocking() || self.rhs.is_blocking()
+  }
 }
 
 impl<VT: Clone + 'static, RT: 'static, ET: 'static> BitOr<Pipeline<VT,RT,ET>> for Pipeline<VT,RT,ET> {
@@ -104,6 +237,28 @@ pub fn pipeline<VT: 'static, RT: 'static, ET: 'static, F: Fn(VT) -> Result<RT, E
   });
 }
 
+struct SimplePipelineWithContext<CT, VT, RT, ET> {
+  context: CT,
+  raw: Arc<dyn Fn(CT, VT) -> Result<RT, ET> + Sync + Send + 'static>
+}
+
+impl<CT: Clone, VT, RT, ET> RawPipeline<VT,RT, ET> for SimplePipelineWithContext<CT, VT, RT, ET> {
+    fn run(&self,value: VT) -> Result<RT, ET> {
+      (self.raw)(self.context.clone(), value)
+    }
+
+    fn node(&self) -> PipelineNode {
+      PipelineNode::leaf("context")
+    }
+}
+
+pub fn context<CT: Clone + Sync + Send + 'static, VT: 'static, RT: 'static, ET: 'static, F: Fn(CT, VT) -> Result<RT, ET> + Sync + Send + 'static>(c: CT, f: F) -> Pipeline<VT,RT,ET> {
+  Pipeline::new(SimplePipelineWithContext{
+    context: c,
+    raw: Arc::new(f),
+  })
+}
+
 struct FilterPipeline<VT,ET: 'static> {
   raw: Arc<dyn Fn(&VT) -> bool + Sync + Send + 'static>,
   error: ET
@@ -117,6 +272,10 @@ impl<VT,ET : Clone> RawPipeline<VT,VT,ET> for FilterPipeline<VT,ET> {
         Err(self.error.clone())
       }
     }
+
+    fn node(&self) -> PipelineNode {
+      PipelineNode::leaf("filter")
+    }
 }
 
 pub fn filter<T: 'static, ET: Clone + Sync + Send + 'static, F: Fn(&T) -> bool + Sync + Send + 'static>(f: F, error: ET) -> Pipeline<T, T, ET> {
@@ -126,6 +285,66 @@ pub fn filter<T: 'static, ET: Clone + Sync + Send + 'static, F: Fn(&T) -> bool +
   });
 }
 
+struct EachPipeline<VT, RT, ET> {
+  inner: Pipeline<VT, RT, ET>
+}
+
+impl<VT: 'static, RT: 'static, ET: 'static> RawPipeline<Vec<VT>,Vec<RT>,ET> for EachPipeline<VT,RT,ET> {
+  fn run(&self,values: Vec<VT>) -> Result<Vec<RT>, ET> {
+    values.into_iter().map(|v| self.inner.run(v)).collect()
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::nested("each", vec![self.inner.node()])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.inner.is_blocking()
+  }
+}
+
+pub fn each<VT: 'static, RT: 'static, ET: 'static>(p: Pipeline<VT,RT,ET>) -> Pipeline<Vec<VT>,Vec<RT>,ET> {
+  Pipeline::new(EachPipeline {
+    inner: p
+  })
+}
+
+struct EachCollectPipeline<VT, RT, ET> {
+  inner: Pipeline<VT, RT, ET>
+}
+
+impl<VT: 'static, RT: 'static, ET: 'static> RawPipeline<Vec<VT>,Vec<RT>,Vec<ET>> for EachCollectPipeline<VT,RT,ET> {
+  fn run(&self,values: Vec<VT>) -> Result<Vec<RT>, Vec<ET>> {
+    let mut results = Vec::with_capacity(values.len());
+    let mut errors = Vec::new();
+    for v in values {
+      match self.inner.run(v) {
+        Ok(r) => results.push(r),
+        Err(e) => errors.push(e),
+      }
+    }
+    if errors.is_empty() {
+      Ok(results)
+    } else {
+      Err(errors)
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::nested("each_collect", vec![self.inner.node()])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.inner.is_blocking()
+  }
+}
+
+pub fn each_collect<VT: 'static, RT: 'static, ET: 'static>(p: Pipeline<VT,RT,ET>) -> Pipeline<Vec<VT>,Vec<RT>,Vec<ET>> {
+  Pipeline::new(EachCollectPipeline {
+    inner: p
+  })
+}
+
 pub trait RawFramework<VT,RT,ET> {
   fn run(&self, pipeline: Pipeline<VT,RT,ET>);
 }
@@ -0,0 +1,215 @@
+use std::{cell::{Cell, RefCell}, collections::HashMap, fmt::Display, iter};
+
+use serde::de::{self, value::{MapDeserializer, SeqDeserializer}, DeserializeOwned, IntoDeserializer, Visitor};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Error(String);
+
+impl Display for Error {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "{}", self.0)
+  }
+}
+
+impl std::error::Error for Error {}
+
+impl de::Error for Error {
+  fn custom<T: Display>(msg: T) -> Self {
+    Error(msg.to_string())
+  }
+}
+
+pub fn from_multi_map<T: DeserializeOwned>(map: &HashMap<String, Vec<String>>) -> Result<T, Error> {
+  T::deserialize(MapDeserializer::new(map.iter().map(|(k, v)| (k.as_str(), Values(v)))))
+}
+
+pub(crate) fn bool_fields<T: DeserializeOwned>() -> Vec<&'static str> {
+  let fields = RefCell::new(&[][..]);
+  let _ = T::deserialize(FieldsProbe(&fields));
+  fields.into_inner().iter().copied().filter(|field| {
+    let found = Cell::new(false);
+    let _ = T::deserialize(MapDeserializer::new(iter::once((*field, BoolProbe(&found)))));
+    found.get()
+  }).collect()
+}
+
+struct FieldsProbe<'a>(&'a RefCell<&'static [&'static str]>);
+
+impl<'de, 'a> de::Deserializer<'de> for FieldsProbe<'a> {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+    Err(Error("probe".to_string()))
+  }
+
+  fn deserialize_struct<V: Visitor<'de>>(self, _name: &'static str, fields: &'static [&'static str], _visitor: V) -> Result<V::Value, Error> {
+    *self.0.borrow_mut() = fields;
+    Err(Error("probe".to_string()))
+  }
+
+  serde::forward_to_deserialize_any! {
+    bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf option unit unit_struct
+    newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+  }
+}
+
+struct BoolProbe<'a>(&'a Cell<bool>);
+
+impl<'de, 'a> IntoDeserializer<'de, Error> for BoolProbe<'a> {
+  type Deserializer = BoolProbe<'a>;
+
+  fn into_deserializer(self) -> Self::Deserializer {
+    self
+  }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for BoolProbe<'a> {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+    Err(Error("probe".to_string()))
+  }
+
+  fn deserialize_bool<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Error> {
+    self.0.set(true);
+    Err(Error("probe".to_string()))
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_some(self)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  serde::forward_to_deserialize_any! {
+    i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf unit unit_struct
+    seq tuple tuple_struct map struct enum identifier ignored_any
+  }
+}
+
+macro_rules! parse_scalar {
+  ($($method:ident => $visit:ident),*) => {
+    $(
+      fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+        let text = self.text()?;
+        visitor.$visit(text.parse().map_err(|e| Error(format!("invalid value {text:?}: {e}")))?)
+      }
+    )*
+  };
+}
+
+struct Value<'a>(&'a str);
+
+impl<'a> Value<'a> {
+  fn text(&self) -> Result<&'a str, Error> {
+    Ok(self.0)
+  }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, Error> for Value<'a> {
+  type Deserializer = Value<'a>;
+
+  fn into_deserializer(self) -> Self::Deserializer {
+    self
+  }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Value<'a> {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_str(self.0)
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_some(self)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_enum(self.0.into_deserializer())
+  }
+
+  parse_scalar!(
+    deserialize_bool => visit_bool, deserialize_char => visit_char,
+    deserialize_i8 => visit_i8, deserialize_i16 => visit_i16, deserialize_i32 => visit_i32, deserialize_i64 => visit_i64,
+    deserialize_u8 => visit_u8, deserialize_u16 => visit_u16, deserialize_u32 => visit_u32, deserialize_u64 => visit_u64,
+    deserialize_f32 => visit_f32, deserialize_f64 => visit_f64
+  );
+
+  serde::forward_to_deserialize_any! {
+    i128 u128 str string bytes byte_buf unit unit_struct seq tuple tuple_struct map struct identifier ignored_any
+  }
+}
+
+struct Values<'a>(&'a [String]);
+
+impl<'a> Values<'a> {
+  fn text(&self) -> Result<&'a str, Error> {
+    self.0.last().map(|v| v.as_str()).ok_or_else(|| Error("missing value".to_string()))
+  }
+}
+
+impl<'de, 'a> IntoDeserializer<'de, Error> for Values<'a> {
+  type Deserializer = Values<'a>;
+
+  fn into_deserializer(self) -> Self::Deserializer {
+    self
+  }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for Values<'a> {
+  type Error = Error;
+
+  fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    if self.0.len() == 1 {
+      visitor.visit_str(&self.0[0])
+    } else {
+      self.deserialize_seq(visitor)
+    }
+  }
+
+  fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_seq(SeqDeserializer::new(self.0.iter().map(|v| Value(v))))
+  }
+
+  fn deserialize_tuple<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value, Error> {
+    self.deserialize_seq(visitor)
+  }
+
+  fn deserialize_str<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_str(self.text()?)
+  }
+
+  fn deserialize_string<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    self.deserialize_str(visitor)
+  }
+
+  fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_some(self)
+  }
+
+  fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_newtype_struct(self)
+  }
+
+  fn deserialize_enum<V: Visitor<'de>>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V) -> Result<V::Value, Error> {
+    visitor.visit_enum(self.text()?.into_deserializer())
+  }
+
+  parse_scalar!(
+    deserialize_bool => visit_bool, deserialize_char => visit_char,
+    deserialize_i8 => visit_i8, deserialize_i16 => visit_i16, deserialize_i32 => visit_i32, deserialize_i64 => visit_i64,
+    deserialize_u8 => visit_u8, deserialize_u16 => visit_u16, deserialize_u32 => visit_u32, deserialize_u64 => visit_u64,
+    deserialize_f32 => visit_f32, deserialize_f64 => visit_f64
+  );
+
+  serde::forward_to_deserialize_any! {
+    i128 u128 bytes byte_buf unit unit_struct tuple_struct map struct identifier ignored_any
+  }
+}
@@ -0,0 +1,171 @@
+use std::{collections::HashMap, str::from_utf8};
+
+use hyper::{body::Bytes, header::{CONTENT_LENGTH, CONTENT_TYPE}, Request};
+use serde::de::DeserializeOwned;
+
+use crate::{de::from_multi_map, http_server::{HttpContext, HttpPipeline, HttpRequest}, pipeline, problem::{IntoResponse, Problem}};
+
+#[derive(Debug, Clone)]
+pub struct Part {
+  pub name: String,
+  pub filename: Option<String>,
+  pub content_type: Option<String>,
+  pub data: Bytes
+}
+
+impl Part {
+  pub fn is_file(&self) -> bool {
+    self.filename.is_some()
+  }
+
+  pub fn text(&self) -> Option<&str> {
+    from_utf8(&self.data).ok()
+  }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Multipart {
+  pub parts: Vec<Part>
+}
+
+impl Multipart {
+  pub fn field(&self, name: &str) -> Option<&str> {
+    self.parts.iter().find(|p| p.name == name && !p.is_file()).and_then(|p| p.text())
+  }
+
+  pub fn file(&self, name: &str) -> Option<&Part> {
+    self.parts.iter().find(|p| p.name == name && p.is_file())
+  }
+
+  pub fn files(&self) -> Vec<&Part> {
+    self.parts.iter().filter(|p| p.is_file()).collect()
+  }
+
+  pub fn fields_as<T: DeserializeOwned>(&self) -> Result<T, Problem> {
+    let mut fields: HashMap<String, Vec<String>> = HashMap::new();
+    for part in self.parts.iter().filter(|p| !p.is_file()) {
+      if let Some(text) = part.text() {
+        fields.entry(part.name.clone()).or_default().push(text.to_string());
+      }
+    }
+    from_multi_map(&fields).map_err(|e| Problem::new(400).with_detail(e))
+  }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MultipartLimits {
+  pub max_parts: usize,
+  pub max_part_size: usize
+}
+
+impl MultipartLimits {
+  fn max_body_size(&self) -> usize {
+    self.max_parts.saturating_mul(self.max_part_size)
+  }
+}
+
+impl Default for MultipartLimits {
+  fn default() -> Self {
+    MultipartLimits {
+      max_parts: 100,
+      max_part_size: 10 * 1024 * 1024
+    }
+  }
+}
+
+fn content_type<T>(r: &Request<HttpContext<T>>) -> String {
+  r.headers().get(CONTENT_TYPE).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string()
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+  if from > haystack.len() {
+    return None;
+  }
+  haystack[from..].windows(needle.len()).position(|w| w == needle).map(|p| p + from)
+}
+
+fn header_param(header: &str, key: &str) -> Option<String> {
+  header.split(';').skip(1).filter_map(|p| p.trim().split_once('=')).find(|(k, _)| k.trim().eq_ignore_ascii_case(key)).map(|(_, v)| v.trim().trim_matches('"').to_string())
+}
+
+fn parse_part(raw: &Bytes) -> Result<Part, Problem> {
+  let split = find(raw, b"\r\n\r\n", 0).ok_or_else(|| Problem::new(400).with_detail("malformed multipart headers"))?;
+  let headers = from_utf8(&raw[..split]).map_err(|e| Problem::new(400).with_detail(e))?;
+  let mut name = None;
+  let mut filename = None;
+  let mut content_type = None;
+  for line in headers.split("\r\n") {
+    let Some((key, value)) = line.split_once(':') else { continue };
+    if key.trim().eq_ignore_ascii_case("content-disposition") {
+      name = header_param(value, "name");
+      filename = header_param(value, "filename");
+    } else if key.trim().eq_ignore_ascii_case("content-type") {
+      content_type = Some(value.trim().to_string());
+    }
+  }
+  Ok(Part {
+    name: name.ok_or_else(|| Problem::new(400).with_detail("multipart part without name"))?,
+    filename,
+    content_type,
+    data: raw.slice(split + 4..)
+  })
+}
+
+pub fn parse_multipart(boundary: &str, body: &Bytes, limits: MultipartLimits) -> Result<Multipart, Problem> {
+  let delimiter = format!("--{boundary}");
+  let delimiter = delimiter.as_bytes();
+  let mut parts = vec![];
+  let mut position = find(body, delimiter, 0).ok_or_else(|| Problem::new(400).with_detail("multipart boundary not found"))? + delimiter.len();
+  loop {
+    if body[position..].starts_with(b"--") {
+      break;
+    }
+    if !body[position..].starts_with(b"\r\n") {
+      return Err(Problem::new(400).with_detail("malformed multipart body"));
+    }
+    let start = position + 2;
+    let end = find(body, &[b"\r\n", delimiter].concat(), start).ok_or_else(|| Problem::new(400).with_detail("unterminated multipart body"))?;
+    if parts.len() >= limits.max_parts {
+      return Err(Problem::new(413).with_detail(format!("more than {} multipart parts", limits.max_parts)));
+    }
+    let part = parse_part(&body.slice(start..end))?;
+    if part.data.len() > limits.max_part_size {
+      return Err(Problem::new(413).with_detail(format!("part {} exceeds {} bytes", part.name, limits.max_part_size)));
+    }
+    parts.push(part);
+    position = end + 2 + delimiter.len();
+  }
+  Ok(Multipart { parts })
+}
+
+pub fn multipart_with(limits: MultipartLimits) -> HttpPipeline<Bytes, HttpRequest<Multipart>> {
+  pipeline(move |r: Request<HttpContext<Bytes>>| {
+    let content_type = content_type(&r);
+    if !content_type.to_lowercase().starts_with("multipart/form-data") {
+      return Err(Problem::new(415).with_detail("expected multipart/form-data").into_response());
+    }
+    let Some(boundary) = header_param(&content_type, "boundary") else {
+      return Err(Problem::new(400).with_detail("multipart boundary missing").into_response());
+    };
+    let content_length = r.headers().get(CONTENT_LENGTH).and_then(|v| v.to_str().ok()).and_then(|v| v.parse::<usize>().ok());
+    if content_length.unwrap_or(r.body().body.len()) > limits.max_body_size() {
+      return Err(Problem::new(413).with_detail(format!("multipart body exceeds {} bytes", limits.max_body_size())).into_response());
+    }
+    let multipart = parse_multipart(&boundary, &r.body().body, limits).map_err(|e| e.into_response())?;
+    Ok(r.map(|c| c.map_body(|_| multipart)))
+  })
+}
+
+pub fn multipart() -> HttpPipeline<Bytes, HttpRequest<Multipart>> {
+  multipart_with(MultipartLimits::default())
+}
+
+pub fn form<T: DeserializeOwned + Send + Sync + 'static>() -> HttpPipeline<Bytes, HttpRequest<T>> {
+  pipeline(|r: Request<HttpContext<Bytes>>| {
+    if !content_type(&r).to_lowercase().starts_with("application/x-www-form-urlencoded") {
+      return Err(Problem::new(415).with_detail("expected application/x-www-form-urlencoded").into_response());
+    }
+    let value = serde_urlencoded::from_bytes::<T>(&r.body().body).map_err(|e| Problem::new(400).with_detail(e).into_response())?;
+    Ok(r.map(|c| c.map_body(|_| value)))
+  })
+}
@@ -0,0 +1,93 @@
+use std::{collections::BTreeMap, fmt::Write};
+
+use serde::Serialize;
+
+use crate::util::escape_quoted;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PipelineNode {
+  pub kind: String,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub name: Option<String>,
+  #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+  pub metadata: BTreeMap<String, String>,
+  #[serde(skip_serializing_if = "Vec::is_empty")]
+  pub children: Vec<PipelineNode>
+}
+
+impl PipelineNode {
+  pub fn leaf(kind: &str) -> PipelineNode {
+    PipelineNode {
+      kind: kind.to_string(),
+      name: None,
+      metadata: BTreeMap::new(),
+      children: vec![]
+    }
+  }
+
+  pub fn branch(kind: &str, children: Vec<PipelineNode>) -> PipelineNode {
+    let children = children.into_iter().flat_map(|child| {
+      if child.kind == kind && child.name.is_none() && child.metadata.is_empty() {
+        child.children
+      } else {
+        vec![child]
+      }
+    }).collect();
+    PipelineNode::nested(kind, children)
+  }
+
+  pub fn nested(kind: &str, children: Vec<PipelineNode>) -> PipelineNode {
+    PipelineNode {
+      children,
+      ..PipelineNode::leaf(kind)
+    }
+  }
+
+  pub fn with_name(self, name: &str) -> PipelineNode {
+    let node = if self.name.is_some() { PipelineNode::branch("group", vec![self]) } else { self };
+    PipelineNode {
+      name: Some(name.to_string()),
+      ..node
+    }
+  }
+
+  pub fn with_metadata(mut self, key: &str, value: &str) -> PipelineNode {
+    self.metadata.insert(key.to_string(), value.to_string());
+    self
+  }
+
+  pub fn label(&self) -> &str {
+    self.name.as_deref().unwrap_or(&self.kind)
+  }
+
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(self).unwrap()
+  }
+
+  pub fn to_dot(&self) -> String {
+    let mut out = String::from("digraph pipeline {\n  node [shape=box];\n");
+    let mut next = 0;
+    self.write_dot(&mut out, &mut next);
+    out.push_str("}\n");
+    out
+  }
+
+  fn write_dot(&self, out: &mut String, next: &mut usize) -> usize {
+    let id = *next;
+    *next += 1;
+    let mut label = escape_quoted(&if self.name.is_some() { format!("{} ({})", self.label(), self.kind) } else { self.kind.clone() });
+    for (k, v) in self.metadata.iter() {
+      label.push_str(&format!("\\n{}={}", escape_quoted(k), escape_quoted(v)));
+    }
+    let shape = match self.kind.as_str() {
+      "and" | "or" => "ellipse",
+      _ => "box",
+    };
+    let _ = writeln!(out, "  n{id} [label=\"{label}\", shape={shape}];");
+    for (i, child) in self.children.iter().enumerate() {
+      let child_id = child.write_dot(out, next);
+      let _ = writeln!(out, "  n{id} -> n{child_id} [label=\"{}\"];", i + 1);
+    }
+    id
+  }
+}
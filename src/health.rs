@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use futures::future::join_all;
+use http_body_util::Full;
+use hyper::{body::Bytes, header::CONTENT_TYPE, Request, Response};
+use serde_json::{json, Map, Value};
+
+use crate::{async_pipeline, http_server::{HttpAsyncPipeline, HttpContext, HttpPipeline, Shutdown}, pipeline, AsyncPipeline, RawAsyncPipeline};
+
+fn json_response(status: u16, body: Value) -> Response<Full<Bytes>> {
+  Response::builder()
+    .status(status)
+    .header(CONTENT_TYPE, "application/json")
+    .body(Full::new(Bytes::from(body.to_string())))
+    .unwrap()
+}
+
+pub fn health<T: 'static>() -> HttpPipeline<T> {
+  pipeline(|_: Request<HttpContext<T>>| Ok(json_response(200, json!({ "status": "ok" }))))
+}
+
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub fn readiness<T: Send + 'static>(checks: Vec<(&str, AsyncPipeline<(), (), String>)>) -> HttpAsyncPipeline<T> {
+  readiness_with_timeout(checks, CHECK_TIMEOUT)
+}
+
+pub fn readiness_with_timeout<T: Send + 'static>(checks: Vec<(&str, AsyncPipeline<(), (), String>)>, timeout: Duration) -> HttpAsyncPipeline<T> {
+  let checks: Vec<(String, AsyncPipeline<(), (), String>)> = checks.into_iter().map(|(name, check)| (name.to_string(), check)).collect();
+  async_pipeline(move |r: Request<HttpContext<T>>| {
+    let checks = checks.clone();
+    let draining = r.body().extension::<Shutdown>().map(|s| s.is_draining()).unwrap_or(false);
+    async move {
+      if draining {
+        return Ok(json_response(503, json!({ "status": "draining", "checks": {} })));
+      }
+      let results = join_all(checks.iter().map(|(_, check)| async move {
+        match tokio::time::timeout(timeout, check.async_run(())).await {
+          Ok(result) => result,
+          Err(_) => Err(format!("timed out after {}ms", timeout.as_millis())),
+        }
+      })).await;
+      let ready = results.iter().all(|r| r.is_ok());
+      let mut report = Map::new();
+      for ((name, _), result) in checks.iter().zip(results) {
+        let result = match result {
+          Ok(_) => json!({ "status": "ok" }),
+          Err(e) => json!({ "status": "error", "error": e }),
+        };
+        report.insert(name.clone(), result);
+      }
+      if ready {
+        Ok(json_response(200, json!({ "status": "ok", "checks": report })))
+      } else {
+        Ok(json_response(503, json!({ "status": "unavailable", "checks": report })))
+      }
+    }
+  })
+}
@@ -1,62 +1,252 @@
-use std::{collections::HashMap, convert::Infallible, error::Error};
+use std::{collections::HashMap, convert::Infallible, error::Error, future::Future, net::SocketAddr, pin::Pin, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Arc}, time::{Duration, Instant}};
 
-use crate::{filter, pipeline, util::from_utf8, AsyncFramework, AsyncPipeline, Pipeline, RawAsyncFramework, RawAsyncPipeline};
-use http_body_util::{BodyExt, Full};
-use hyper::{body::{Body, Bytes, Incoming}, header::HeaderValue, server::conn::http1, service::service_fn, Method, Response};
+use crate::{async_pipeline, de::from_multi_map, filter, graph::PipelineNode, guard, pipeline, problem::{IntoResponse, Problem}, util::from_utf8, AsyncFramework, AsyncPipeline, Branch, BranchError, Pipeline, RawAsyncFramework, RawBranch, RawAsyncPipeline, Resources};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{body::{Body, Bytes, Incoming}, header::HeaderValue, http::Extensions, server::conn::http1, service::service_fn, Method, Response, Uri};
 use hyper_util::rt::TokioIo;
+use percent_encoding::percent_decode_str;
 use regex::Regex;
-use tokio::net::TcpListener;
+use tokio::{net::TcpListener, sync::Notify, task::JoinSet};
 use async_trait::async_trait;
+use serde::de::DeserializeOwned;
 use serde_urlencoded::from_str;
+use tracing::Instrument;
 
 pub use hyper::Request;
 
-struct HttpServer {
-  address: String
+#[derive(Default)]
+struct ShutdownInner {
+  draining: AtomicBool,
+  notify: Notify
+}
+
+#[derive(Clone, Default)]
+pub struct Shutdown {
+  inner: Arc<ShutdownInner>
+}
+
+impl Shutdown {
+  pub fn new() -> Shutdown {
+    Shutdown::default()
+  }
+
+  pub fn trigger(&self) {
+    self.inner.draining.store(true, Ordering::SeqCst);
+    self.inner.notify.notify_waiters();
+  }
+
+  pub fn is_draining(&self) -> bool {
+    self.inner.draining.load(Ordering::SeqCst)
+  }
+
+  pub async fn wait(&self) {
+    let notified = self.inner.notify.notified();
+    if self.is_draining() {
+      return;
+    }
+    notified.await;
+  }
+}
+
+pub struct HttpServer {
+  address: String,
+  resources: Resources,
+  shutdown: Shutdown,
+  drain_delay: Duration,
+  max_body_size: usize,
+  connections: AtomicU64
+}
+
+impl HttpServer {
+  pub fn new(address: &str) -> HttpServer {
+    HttpServer {
+      address: address.to_string(),
+      resources: Resources::new(),
+      shutdown: Shutdown::new(),
+      drain_delay: Duration::ZERO,
+      max_body_size: usize::MAX,
+      connections: AtomicU64::new(0)
+    }
+  }
+
+  pub fn shutdown(self, shutdown: Shutdown) -> HttpServer {
+    HttpServer {
+      shutdown,
+      ..self
+    }
+  }
+
+  pub fn drain_delay(self, drain_delay: Duration) -> HttpServer {
+    HttpServer {
+      drain_delay,
+      ..self
+    }
+  }
+
+  pub fn max_body_size(self, max_body_size: usize) -> HttpServer {
+    HttpServer {
+      max_body_size,
+      ..self
+    }
+  }
+
+  pub fn resource<T: Send + Sync + 'static>(self, value: T) -> HttpServer {
+    HttpServer {
+      resources: self.resources.with(value),
+      ..self
+    }
+  }
+
+  pub fn resources(self, resources: Resources) -> HttpServer {
+    HttpServer {
+      resources,
+      ..self
+    }
+  }
+
+  pub fn build(self) -> AsyncFramework<HttpRequest<Bytes>, HttpResponse, HttpResponse> {
+    AsyncFramework::new(self)
+  }
 }
 
 #[async_trait]
 impl RawAsyncFramework<Request<HttpContext<Bytes>>,Response<Full<Bytes>>,Response<Full<Bytes>>> for HttpServer {
   async fn run(&self, pipeline: AsyncPipeline<Request<HttpContext<Bytes>>,Response<Full<Bytes>>,Response<Full<Bytes>>>) {
     let listener = TcpListener::bind(&self.address).await.unwrap();
-    loop {
-      let (tcp, _) = listener.accept().await.unwrap();
-      let io = TokioIo::new(tcp);
-      if let Err(err) = http1::Builder::new()
-          .serve_connection(io, service_fn(|req| {
-            async {
-              Ok::<_,Infallible>(match (Ok(req) & to_bytes() & wrap_context() & pipeline.clone()).await {
-                Ok(a) => a.clone(),
-                Err(a) => a.clone(),
-              })
-            }
-          }))
-          .await
-      {
-          println!("Error serving connection: {:?}", err);
+    let mut extensions = Extensions::new();
+    extensions.insert(self.resources.clone());
+    extensions.insert(self.shutdown.clone());
+    let shutdown = self.shutdown.clone();
+    let drain_delay = self.drain_delay;
+    let max_body_size = self.max_body_size;
+    let stop = move || {
+      let shutdown = shutdown.clone();
+      async move {
+        shutdown.wait().await;
+        tokio::time::sleep(drain_delay).await;
       }
+    };
+    let accepting = stop();
+    tokio::pin!(accepting);
+    let mut tasks = JoinSet::new();
+    loop {
+      let (tcp, remote_addr) = tokio::select! {
+        accepted = listener.accept() => accepted.unwrap(),
+        Some(_) = tasks.join_next() => continue,
+        _ = &mut accepting => break,
+      };
+      let connection = ConnectionInfo::new(self.connections.fetch_add(1, Ordering::Relaxed), remote_addr, tcp.local_addr().unwrap());
+      let extensions = extensions.clone();
+      let pipeline = pipeline.clone();
+      let stop = stop();
+      tasks.spawn(async move {
+        let io = TokioIo::new(tcp);
+        let conn = http1::Builder::new()
+            .serve_connection(io, service_fn(|req| {
+              let connection = connection.clone();
+              let span = tracing::info_span!("request", connection = connection.id, method = %req.method(), path = %req.uri().path());
+              async {
+                Ok::<_,Infallible>(match (Ok(req) & to_bytes(max_body_size) & wrap_context(connection, extensions.clone()) & pipeline.clone()).await {
+                  Ok(a) => a.clone(),
+                  Err(a) => a.clone(),
+                })
+              }.instrument(span)
+            }));
+        tokio::pin!(conn);
+        tokio::pin!(stop);
+        let result = tokio::select! {
+          result = conn.as_mut() => result,
+          _ = &mut stop => {
+            conn.as_mut().graceful_shutdown();
+            conn.await
+          }
+        };
+        if let Err(err) = result {
+            tracing::error!(remote_addr = %remote_addr, "error serving connection: {:?}", err);
+        }
+      });
     }
+    drop(listener);
+    while tasks.join_next().await.is_some() {}
   }
 }
 
 pub fn http_server(address: &str) -> AsyncFramework<Request<HttpContext<Bytes>>,Response<Full<Bytes>>,Response<Full<Bytes>>> {
-  AsyncFramework::new(HttpServer {
-    address: address.to_string()
-  })
+  HttpServer::new(address).build()
 }
 
 pub type HttpResponse = Response<Full<Bytes>>;
-// pub type HttpAsyncPipeline<VT,RT> = AsyncPipeline<Request<HttpContext<VT>>, RT, HttpResponse>;
+pub type HttpRequest<T> = Request<HttpContext<T>>;
+pub type HttpPipeline<T, RT = HttpResponse> = Pipeline<HttpRequest<T>, RT, HttpResponse>;
+pub type HttpAsyncPipeline<T, RT = HttpResponse> = AsyncPipeline<HttpRequest<T>, RT, HttpResponse>;
+
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct TlsInfo {
+  pub server_name: Option<String>,
+  pub protocol_version: Option<String>,
+  pub alpn_protocol: Option<Vec<u8>>
+}
+
+impl TlsInfo {
+  pub fn new(server_name: Option<String>, protocol_version: Option<String>, alpn_protocol: Option<Vec<u8>>) -> TlsInfo {
+    TlsInfo {
+      server_name,
+      protocol_version,
+      alpn_protocol
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct ConnectionInfo {
+  pub id: u64,
+  pub remote_addr: SocketAddr,
+  pub local_addr: SocketAddr,
+  // always None for the plain tcp listener; filled in by tls-terminating frameworks
+  pub tls: Option<TlsInfo>
+}
+
+impl ConnectionInfo {
+  pub fn new(id: u64, remote_addr: SocketAddr, local_addr: SocketAddr) -> ConnectionInfo {
+    ConnectionInfo {
+      id,
+      remote_addr,
+      local_addr,
+      tls: None
+    }
+  }
+
+  pub fn with_tls(self, tls: TlsInfo) -> ConnectionInfo {
+    ConnectionInfo {
+      tls: Some(tls),
+      ..self
+    }
+  }
+}
+
+#[derive(Debug, Clone)]
+struct ParamSnapshot {
+  id: u64,
+  params: HashMap<String,String>,
+  multi_params: HashMap<String,Vec<String>>
+}
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub struct HttpContext<T> {
   pub params: HashMap<String,String>,
+  multi_params: HashMap<String,Vec<String>>,
+  snapshots: Vec<ParamSnapshot>,
+  pub connection: Option<ConnectionInfo>,
+  pub extensions: Extensions,
   pub body: T
 }
 
 impl<T: Clone> Clone for HttpContext<T>  {
     fn clone(&self) -> Self {
-        Self { params: self.params.clone(), body: self.body.clone() }
+        Self { params: self.params.clone(), multi_params: self.multi_params.clone(), snapshots: self.snapshots.clone(), connection: self.connection.clone(), extensions: self.extensions.clone(), body: self.body.clone() }
     }
 }
 
@@ -64,13 +254,171 @@ impl<T> HttpContext<T> {
   pub fn new(params: HashMap<String,String>, body: T) -> HttpContext<T>{
     HttpContext {
       params,
+      multi_params: HashMap::new(),
+      snapshots: vec![],
+      connection: None,
+      extensions: Extensions::new(),
       body
     }
   }
+
+  pub fn map_body<U, F: FnOnce(T) -> U>(self, f: F) -> HttpContext<U> {
+    HttpContext {
+      params: self.params,
+      multi_params: self.multi_params,
+      snapshots: self.snapshots,
+      connection: self.connection,
+      extensions: self.extensions,
+      body: f(self.body)
+    }
+  }
+
+  pub fn insert_param(&mut self, key: &str, value: &str) {
+    self.params.insert(key.to_string(), value.to_string());
+    self.multi_params.entry(key.to_string()).or_default().push(value.to_string());
+  }
+
+  fn snapshot_params(&mut self, id: u64) {
+    self.snapshots.push(ParamSnapshot { id, params: self.params.clone(), multi_params: self.multi_params.clone() });
+  }
+
+  fn restore_params(&mut self, id: u64) {
+    let Some(i) = self.snapshots.iter().rposition(|s| s.id == id) else { return };
+    if let Some(snapshot) = self.snapshots.drain(i..).next() {
+      self.params = snapshot.params;
+      self.multi_params = snapshot.multi_params;
+    }
+  }
+
+  pub fn param_values(&self, key: &str) -> Vec<String> {
+    let Some(value) = self.params.get(key) else { return vec![] };
+    match self.multi_params.get(key) {
+      Some(values) if values.last() == Some(value) => values.clone(),
+      _ => vec![value.clone()],
+    }
+  }
+
+  pub fn params_as<P: DeserializeOwned>(&self) -> Result<P, Problem> {
+    let params = self.params.keys().map(|k| (k.clone(), self.param_values(k))).collect();
+    from_multi_map(&params).map_err(|e| Problem::new(400).with_detail(e))
+  }
+
+  pub fn remote_addr(&self) -> Option<SocketAddr> {
+    self.connection.as_ref().map(|c| c.remote_addr)
+  }
+
+  pub fn local_addr(&self) -> Option<SocketAddr> {
+    self.connection.as_ref().map(|c| c.local_addr)
+  }
+
+  pub fn connection_id(&self) -> Option<u64> {
+    self.connection.as_ref().map(|c| c.id)
+  }
+
+  pub fn tls(&self) -> Option<&TlsInfo> {
+    self.connection.as_ref().and_then(|c| c.tls.as_ref())
+  }
+
+  pub fn extension<E: Send + Sync + 'static>(&self) -> Option<&E> {
+    self.extensions.get::<E>()
+  }
+
+  pub fn insert_extension<E: Clone + Send + Sync + 'static>(&mut self, value: E) -> Option<E> {
+    self.extensions.insert(value)
+  }
+
+  pub fn resource<R: Send + Sync + 'static>(&self) -> Option<Arc<R>> {
+    self.extension::<Resources>().and_then(|r| r.get::<R>())
+  }
+}
+
+pub fn extend<E: Clone + Send + Sync + 'static, T: 'static, F: Fn(&HttpRequest<T>) -> Result<E, HttpResponse> + Sync + Send + 'static>(f: F) -> HttpPipeline<T, HttpRequest<T>> {
+  pipeline(move |mut r: Request<HttpContext<T>>| {
+    let value = f(&r)?;
+    r.body_mut().insert_extension(value);
+    Ok(r)
+  })
+}
+
+pub fn async_extend<E: Clone + Send + Sync + 'static, T: Send + 'static, F: Fn(&HttpRequest<T>) -> Pin<Box<dyn Future<Output = Result<E, HttpResponse>> + Send + 'static>> + Sync + Send + 'static>(f: F) -> HttpAsyncPipeline<T, HttpRequest<T>> {
+  async_pipeline(move |mut r: Request<HttpContext<T>>| {
+    let value = f(&r);
+    async move {
+      r.body_mut().insert_extension(value.await?);
+      Ok(r)
+    }
+  })
+}
+
+pub fn with_resource<R: Send + Sync + 'static, T: 'static, RT: 'static, F: Fn(Arc<R>, HttpRequest<T>) -> Result<RT, HttpResponse> + Sync + Send + 'static>(f: F) -> HttpPipeline<T, RT> {
+  pipeline(move |r: Request<HttpContext<T>>| match r.body().resource::<R>() {
+    Some(resource) => f(resource, r),
+    None => Err(Problem::new(500).with_detail(format!("missing resource {}", std::any::type_name::<R>())).into_response()),
+  })
+}
+
+pub fn async_with_resource<R: Send + Sync + 'static, T: Send + 'static, RT: Send + 'static, FT: Future<Output = Result<RT, HttpResponse>> + Send + 'static, F: Fn(Arc<R>, HttpRequest<T>) -> FT + Sync + Send + 'static>(f: F) -> HttpAsyncPipeline<T, RT> {
+  let f = Arc::new(f);
+  async_pipeline(move |r: Request<HttpContext<T>>| {
+    let f = f.clone();
+    async move {
+      match r.body().resource::<R>() {
+        Some(resource) => f(resource, r).await,
+        None => Err(Problem::new(500).with_detail(format!("missing resource {}", std::any::type_name::<R>())).into_response()),
+      }
+    }
+  })
+}
+
+pub fn require_extension<E: Send + Sync + 'static, T: 'static>() -> HttpPipeline<T, HttpRequest<T>> {
+  pipeline(|r: Request<HttpContext<T>>| match r.body().extension::<E>() {
+    Some(_) => Ok(r),
+    None => Err(Problem::new(500).with_detail(format!("missing extension {}", std::any::type_name::<E>())).into_response()),
+  })
+}
+
+struct AccessLog<T> {
+  inner: HttpAsyncPipeline<T>
+}
+
+#[async_trait]
+impl<T: Send + 'static> RawAsyncPipeline<Request<HttpContext<T>>, Response<Full<Bytes>>, Response<Full<Bytes>>> for AccessLog<T> {
+  async fn async_run(&self, r: Request<HttpContext<T>>) -> Result<Response<Full<Bytes>>, Response<Full<Bytes>>> {
+    let method = r.method().clone();
+    let path = r.uri().path().to_string();
+    let remote_addr = r.body().remote_addr();
+    let start = Instant::now();
+    let result = self.inner.async_run(r).await;
+    let status = match &result {
+      Ok(res) => res.status(),
+      Err(res) => res.status(),
+    };
+    tracing::info!(
+      method = %method,
+      path = %path,
+      status = status.as_u16(),
+      latency_ms = start.elapsed().as_secs_f64() * 1000.0,
+      remote_addr = ?remote_addr,
+      "access"
+    );
+    result
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("access_log", vec![self.inner.node()])
+  }
+}
+
+pub fn access_log<T: Send + 'static>(pipeline: HttpAsyncPipeline<T>) -> HttpAsyncPipeline<T> {
+  AsyncPipeline::new(AccessLog {
+    inner: pipeline
+  })
 }
 
 pub fn method_is<T: Clone + 'static>(method: Method) -> Pipeline<Request<HttpContext<T>>, Request<HttpContext<T>>, Response<Full<Bytes>>>{
-  filter(move|r : &Request<HttpContext<T>>| r.method() == &method, Response::builder().status(405).body(Full::new(Bytes::from(""))).unwrap())
+  let name = method.to_string();
+  filter(move|r : &Request<HttpContext<T>>| r.method() == &method, Problem::new(405).into_response())
+    .meta("http.method", &name)
 }
 
 pub fn http_get<T: Clone + 'static>() -> Pipeline<Request<HttpContext<T>>, Request<HttpContext<T>>, Response<Full<Bytes>>> {
@@ -90,7 +438,12 @@ pub fn http_delete<T: Clone + 'static>() -> Pipeline<Request<HttpContext<T>>, Re
 }
 
 pub fn http_error<T: Clone + 'static>(status: u16, message: &'static str) -> Result<T, Response<Full<Bytes>>> {
-  Err(Response::builder().status(status).body(Full::new(Bytes::from(message))).unwrap())
+  let problem = Problem::new(status);
+  if message.is_empty() {
+    Err(problem.into_response())
+  } else {
+    Err(problem.with_detail(message).into_response())
+  }
 }
 
 pub fn http_ok<ET: Clone + 'static>(status: u16, message: &'static str) -> Result<Response<Full<Bytes>>, ET> {
@@ -105,28 +458,38 @@ pub fn set_header(key: &'static str, value: &'static str) -> Pipeline<Response<F
   })
 }
 
-struct ToByte;
+struct ToByte {
+  limit: usize
+}
 
 #[async_trait]
 impl RawAsyncPipeline<Request<Incoming>, Request<Bytes>, Response<Full<Bytes>>> for ToByte {
   async fn async_run(&self,r: Request<Incoming>) -> Result<Request<Bytes>, Response<Full<Bytes>>> {
     let mut body: Option<Incoming> = None;
     let r = r.map(|b| body = Some(b));
-    match body.unwrap().collect().await {
+    match Limited::new(body.unwrap(), self.limit).collect().await {
       Ok(rr) => {
         Ok(r.map(|_| rr.to_bytes()))
       },
-      Err(_) => Err(Response::builder().status(400).body(Full::new(Bytes::from(""))).unwrap()),
+      Err(e) if e.is::<LengthLimitError>() => Err(Problem::new(413).with_detail(format!("request body exceeds {} bytes", self.limit)).into_response()),
+      Err(e) => Err(Problem::new(400).with_detail(e).into_response()),
     }
   }
 }
 
-fn to_bytes() -> AsyncPipeline<Request<Incoming>,Request<Bytes>, Response<Full<Bytes>>> {
-  AsyncPipeline::new(ToByte)
+fn to_bytes(limit: usize) -> AsyncPipeline<Request<Incoming>,Request<Bytes>, Response<Full<Bytes>>> {
+  AsyncPipeline::new(ToByte { limit })
 }
 
-fn wrap_context<T: Send  + 'static>() -> Pipeline<Request<T>,Request<HttpContext<T>>, Response<Full<Bytes>>> {
-  pipeline(|r: Request<T>| Ok(r.map(|body| HttpContext::new(HashMap::new(), body))))
+fn wrap_context<T: Send  + 'static>(connection: ConnectionInfo, extensions: Extensions) -> Pipeline<Request<T>,Request<HttpContext<T>>, Response<Full<Bytes>>> {
+  pipeline(move |r: Request<T>| Ok(r.map(|body| HttpContext {
+    params: HashMap::new(),
+    multi_params: HashMap::new(),
+    snapshots: vec![],
+    connection: Some(connection.clone()),
+    extensions: extensions.clone(),
+    body
+  })))
 }
 
 pub fn to_string() -> Pipeline<Request<HttpContext<Bytes>>, Request<HttpContext<String>>, Response<Full<Bytes>>> {
@@ -138,64 +501,235 @@ pub fn from_body<T: Body + Send + Sync + 'static>() -> Pipeline<Request<HttpCont
 }
 
 pub fn request<VT : Send + Sync + 'static,RT:Send + Sync + 'static,ET: Send + Sync + Error + 'static>(pipline: Pipeline<VT,RT, ET>) -> Pipeline<Request<HttpContext<VT>>,Request<HttpContext<RT>>, Response<Full<Bytes>>> {
+  let blocking = pipline.is_blocking();
   pipeline(move |r: Request<HttpContext<VT>>| {
-    let req = r.map(|v: HttpContext<VT>| HttpContext::new(v.params,Ok(v.body) & pipline.clone()));
+    let req = r.map(|v: HttpContext<VT>| v.map_body(|b| Ok(b) & pipline.clone()));
     match &req.body().body {
-      Ok(_) => Ok(req.map(|r| HttpContext::new(r.params, r.body.unwrap()))),
-      Err(e) => Err(Response::builder().status(400).body(Full::new(Bytes::from(e.to_string()))).unwrap())
+      Ok(_) => Ok(req.map(|r| r.map_body(|b| b.unwrap()))),
+      Err(e) => Err(Problem::new(400).with_detail(e).into_response())
+    }
+  }).blocking_if(blocking)
+}
+
+fn split_pattern(path: &str) -> Vec<String> {
+  let mut segments = vec![];
+  let mut current = String::new();
+  let mut depth = 0;
+  for c in path.trim_start_matches('/').chars() {
+    match c {
+      '<' => depth += 1,
+      '>' => depth -= 1,
+      '/' if depth == 0 => {
+        segments.push(current.clone());
+        current.clear();
+        continue;
+      },
+      _ => {},
+    }
+    current.push(c);
+  }
+  segments.push(current);
+  segments
+}
+
+fn constraint_regex(constraint: &str) -> (&str, &str) {
+  match constraint {
+    "int" => ("-?[0-9]+", "int"),
+    "uint" => ("[0-9]+", "int"),
+    "alpha" => ("[A-Za-z]+", "string"),
+    "alnum" => ("[A-Za-z0-9]+", "string"),
+    "uuid" => ("[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{4}-[0-9a-fA-F]{12}", "string"),
+    custom => (custom, "string"),
+  }
+}
+
+struct PathPattern {
+  regex: String,
+  template: String,
+  params: Vec<(String, String)>
+}
+
+fn split_name(segment: &str) -> (&str, &str) {
+  segment.split_at(segment.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(segment.len()))
+}
+
+fn compile_path(path: &str) -> PathPattern {
+  let mut regex = String::from("^");
+  let mut template = String::new();
+  let mut params = vec![];
+  for segment in split_pattern(path) {
+    if let Some(rest) = segment.strip_prefix('*') {
+      let (name, literal) = split_name(rest);
+      assert!(!name.is_empty(), "missing wildcard name in path pattern {path}");
+      regex.push_str(&format!("/(?<{name}>.*){}", regex::escape(literal)));
+      template.push_str(&format!("/{{{name}}}{literal}"));
+      params.push((name.to_string(), "string".to_string()));
+    } else if let Some(rest) = segment.strip_prefix(':') {
+      let (name, rest) = split_name(rest);
+      assert!(!name.is_empty(), "missing parameter name in path pattern {path}");
+      let ((pattern, kind), rest) = match rest.strip_prefix('<').and_then(|r| r.rsplit_once('>')) {
+        Some((constraint, rest)) => (constraint_regex(constraint), rest),
+        None => (("[^/]+", "string"), rest),
+      };
+      let (optional, literal) = match rest.strip_prefix('?') {
+        Some(literal) => (true, literal),
+        None => (false, rest),
+      };
+      let capture = format!("/(?<{name}>{pattern}){}", regex::escape(literal));
+      if optional {
+        regex.push_str(&format!("(?:{capture})?"));
+      } else {
+        regex.push_str(&capture);
+      }
+      template.push_str(&format!("/{{{name}}}{literal}"));
+      params.push((name.to_string(), if optional { format!("{kind}?") } else { kind.to_string() }));
+    } else {
+      regex.push('/');
+      regex.push_str(&regex::escape(&segment));
+      template.push('/');
+      template.push_str(&segment);
+    }
+  }
+  regex.push('$');
+  PathPattern { regex, template, params }
+}
+
+pub fn method_guard<T: 'static>(method: Method) -> Branch<HttpRequest<T>, HttpRequest<T>, HttpResponse> {
+  let name = method.to_string();
+  guard(move |r: &Request<HttpContext<T>>| r.method() == method, Problem::new(405).into_response())
+    .meta("http.method", &name)
+}
+
+struct PathBranch {
+  id: u64,
+  path_re: Regex,
+  path_params: Vec<String>
+}
+
+impl<T> RawBranch<HttpRequest<T>, HttpRequest<T>, HttpResponse> for PathBranch {
+  fn run(&self, mut r: HttpRequest<T>) -> Result<HttpRequest<T>, BranchError<HttpRequest<T>, HttpResponse>> {
+    let captured: Vec<(String, String)> = match self.path_re.captures(r.uri().path()) {
+      Some(c) => self.path_params.iter().filter_map(|pp| c.name(pp).map(|m| (pp.clone(), m.as_str().to_string()))).collect(),
+      None => return Err(BranchError::Rejected(r, Problem::new(404).into_response())),
+    };
+    let mut decoded = vec![];
+    for (k, v) in captured.iter() {
+      let v = percent_decode_str(v).decode_utf8().map_err(|e| BranchError::Failed(Problem::new(400).with_detail(e).into_response()))?;
+      decoded.push((k, v.to_string()));
+    }
+    r.body_mut().snapshot_params(self.id);
+    for (k, v) in decoded.iter() {
+      r.body_mut().insert_param(k, v);
     }
+    Ok(r)
+  }
+
+  fn restore(&self, mut r: HttpRequest<T>) -> HttpRequest<T> {
+    r.body_mut().restore_params(self.id);
+    r
+  }
+}
+
+pub fn path_guard<T: 'static>(path: &str) -> Branch<HttpRequest<T>, HttpRequest<T>, HttpResponse> {
+  static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+  let pattern = compile_path(path);
+  let params_meta = pattern.params.iter().map(|(name, kind)| format!("{name}:{kind}")).collect::<Vec<String>>().join(",");
+  Branch::new(PathBranch {
+    id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+    path_re: Regex::new(&pattern.regex).unwrap_or_else(|e| panic!("invalid path pattern {path}: {e}")),
+    path_params: pattern.params.iter().map(|(name, _)| name.clone()).collect()
   })
+    .meta("http.path", path)
+    .meta("http.template", &pattern.template)
+    .meta("http.params", &params_meta)
 }
 
 pub fn from_path<T: 'static>(path: &str) -> Pipeline<Request<HttpContext<T>>, Request<HttpContext<T>>, Response<Full<Bytes>>> {
-  let path_re_base = Regex::new("/:(\\w+)").unwrap();
-  let path_params: Vec<String> = path_re_base.clone().captures_iter(path).map(|m| m.get(1).unwrap().as_str().to_string()).collect();
-  let re_text = path_params.iter().fold(format!("^{path}$"),|p: String,pp| p.replace(&format!(":{pp}"), &format!("(?<{pp}>[^/]+)")));
-  let path_re = Regex::new(&re_text).unwrap();
-  pipeline(move |r: Request<HttpContext<T>>| {
-    let rr = &r;
-    let params = rr.body().params.clone();
-    let new_params = match path_re.clone().captures(rr.uri().path()) {
-        Some(r) => {
-          let mut new_paramas = params.clone();
-          for pp in path_params.clone().iter() {
-            if let Some(r) = r.name(pp) {
-              new_paramas.insert(pp.clone(), r.as_str().to_string());
-            }
-          }
-          Ok(new_paramas)
-        },
-        None => Err(Response::builder().status(404).body(Full::new(Bytes::from(""))).unwrap()),
+  path_guard(path).pipeline()
+}
+
+pub fn most_specific(lhs: &Response<Full<Bytes>>, rhs: &Response<Full<Bytes>>) -> bool {
+  let rank = |r: &Response<Full<Bytes>>| match r.status().as_u16() {
+    404 => 0,
+    405 => 1,
+    _ => 2,
+  };
+  rank(lhs) > rank(rhs)
+}
+
+#[derive(Debug, Clone)]
+pub struct OriginalUri(pub Uri);
+
+fn strip_prefix<T: 'static>(prefix: &str) -> HttpPipeline<T, HttpRequest<T>> {
+  let prefix = prefix.trim_end_matches('/').to_string();
+  pipeline(move |mut r: Request<HttpContext<T>>| {
+    let rest = match r.uri().path().strip_prefix(prefix.as_str()) {
+      Some("") => "/".to_string(),
+      Some(rest) if rest.starts_with('/') => rest.to_string(),
+      _ => return Err(Problem::new(404).into_response()),
+    };
+    let path_and_query = match r.uri().query() {
+      Some(query) => format!("{rest}?{query}"),
+      None => rest,
     };
-    match new_params {
-      Ok(params) => Ok(r.map(|old| HttpContext::new(params, old.body))),
-      Err(e) => Err(e)
+    let original = r.uri().clone();
+    let mut parts = original.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().map_err(|e| Problem::new(400).with_detail(e).into_response())?);
+    *r.uri_mut() = Uri::from_parts(parts).map_err(|e| Problem::new(400).with_detail(e).into_response())?;
+    if r.body().extension::<OriginalUri>().is_none() {
+      r.body_mut().insert_extension(OriginalUri(original));
     }
+    Ok(r)
   })
 }
 
+pub fn nest<T: 'static, RT: 'static>(prefix: &str, pipeline: HttpPipeline<T, RT>) -> HttpPipeline<T, RT> {
+  (strip_prefix(prefix) & pipeline).meta("http.prefix", prefix.trim_end_matches('/'))
+}
+
+pub fn async_nest<T: Send + 'static, RT: Send + 'static>(prefix: &str, pipeline: HttpAsyncPipeline<T, RT>) -> HttpAsyncPipeline<T, RT> {
+  (strip_prefix(prefix) & pipeline).meta("http.prefix", prefix.trim_end_matches('/'))
+}
+
 pub fn from_query<T: 'static>() -> Pipeline<Request<HttpContext<T>>, Request<HttpContext<T>>, Response<Full<Bytes>>> {
-  pipeline(|r: Request<HttpContext<T>>| {
-    let rr = &r;
-    let params = rr.body().params.clone();
-    let new_params = match from_str::<HashMap<String,String>>(rr.uri().query().unwrap_or_default()) {
-      Ok(v) => {
-        let mut new_paramas = params.clone();
-        for (k,v) in v.iter() {
-          new_paramas.insert(k.clone(), v.clone());
-        }
-        Ok(new_paramas)
-      },
-      Err(e) => Err(Response::builder().status(400).body(Full::new(Bytes::from(e.to_string()))).unwrap()),
-    };
+  pipeline(|mut r: Request<HttpContext<T>>| {
+    let pairs = from_str::<Vec<(String,String)>>(r.uri().query().unwrap_or_default()).map_err(|e| Problem::new(400).with_detail(e).into_response())?;
+    for (k, v) in pairs.iter() {
+      r.body_mut().insert_param(k, v);
+    }
+    Ok(r)
+  })
+}
 
-    match new_params {
-      Ok(params) => Ok(r.map(|old| HttpContext::new(params, old.body))),
-      Err(e) => Err(e)
+pub fn from_header<T: 'static>(name: &str) -> HttpPipeline<T, HttpRequest<T>> {
+  let name = name.to_lowercase();
+  pipeline(move |mut r: Request<HttpContext<T>>| {
+    let values: Vec<String> = r.headers().get_all(name.as_str()).iter().filter_map(|v| v.to_str().ok()).map(|v| v.to_string()).collect();
+    if values.is_empty() {
+      return Err(Problem::new(400).with_detail(format!("missing header {name}")).into_response());
     }
+    for v in values.iter() {
+      r.body_mut().insert_param(&name, v);
+    }
+    Ok(r)
   })
 }
 
+pub fn headers_as<H: DeserializeOwned + Clone + Send + Sync + 'static, T: 'static>() -> HttpPipeline<T, HttpRequest<T>> {
+  extend(|r: &Request<HttpContext<T>>| {
+    let mut headers: HashMap<String, Vec<String>> = HashMap::new();
+    for (k, v) in r.headers().iter() {
+      if let Ok(v) = v.to_str() {
+        headers.entry(k.as_str().to_string()).or_default().push(v.to_string());
+      }
+    }
+    from_multi_map::<H>(&headers).map_err(|e| Problem::new(400).with_detail(e).into_response())
+  })
+}
+
+pub fn params_as<P: DeserializeOwned + Clone + Send + Sync + 'static, T: 'static>() -> HttpPipeline<T, HttpRequest<T>> {
+  extend(|r: &Request<HttpContext<T>>| r.body().params_as::<P>().map_err(|e| e.into_response()))
+}
 
 pub fn to_body<T: Into<Bytes> + Send + Sync + 'static>() -> Pipeline<T, Response<Full<Bytes>>, Response<Full<Bytes>>> {
   pipeline(|s: T| Ok(Response::builder().status(200).body(Full::new(s.into())).unwrap()))
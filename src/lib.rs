@@ -1,18 +1,37 @@
 //! # lopin - library of pipeline input
 //!
-//! `lopin` is a Query framework featuring a two-way pipeline and resources abstracted as stores. 
+//! `lopin` is a Query framework featuring a two-way pipeline and resources abstracted as stores.
 //!
 
+// http pipelines use the response itself as their error type.
+#![allow(clippy::result_large_err)]
+
 mod core;
 mod async_core;
+mod branch;
+mod resources;
+mod de;
 pub mod util;
 pub mod testing;
 pub mod json;
 pub mod http_server;
 pub mod command;
+pub mod metrics;
+pub mod health;
+pub mod graph;
+pub mod openapi;
+pub mod validate;
+pub mod problem;
+pub mod negotiate;
+pub mod form;
+pub mod stream;
+pub mod cli;
+pub mod typed;
 
 pub use core::*;
 pub use async_core::*;
+pub use branch::*;
+pub use resources::*;
 
 #[cfg(test)]
 mod tests {
@@ -70,4 +89,875 @@ mod tests {
         let pipeline  = filter(|v| v % 2 == 0, 1) & pipeline(|v| Ok(v / 2)) | pipeline(|v| Ok(v) );
         testing::test_ok(9, 9) ^ pipeline;
     }
+    #[test]
+    fn http_context_keeps_connection() {
+        use http_server::*;
+        let mut context = HttpContext::new(std::collections::HashMap::new(), ());
+        context.connection = Some(ConnectionInfo::new(7, "127.0.0.1:50000".parse().unwrap(), "127.0.0.1:8080".parse().unwrap()));
+        let req = Request::builder().uri("/users/10").body(context).unwrap();
+        let req = (Ok(req) & from_path("/users/:id")).unwrap();
+        assert_eq!(req.body().params.get("id"), Some(&"10".to_string()));
+        assert_eq!(req.body().connection_id(), Some(7));
+        assert_eq!(req.body().remote_addr(), Some("127.0.0.1:50000".parse().unwrap()));
+        assert!(req.body().tls().is_none());
+        let tls = ConnectionInfo::new(8, "127.0.0.1:50001".parse().unwrap(), "127.0.0.1:8443".parse().unwrap()).with_tls(TlsInfo::new(Some("example.com".to_string()), None, Some(b"h2".to_vec())));
+        let mut context = HttpContext::new(std::collections::HashMap::new(), ());
+        context.connection = Some(tls);
+        assert_eq!(context.tls().and_then(|t| t.server_name.clone()), Some("example.com".to_string()));
+    }
+    #[test]
+    fn http_context_extensions() {
+        use http_server::*;
+        #[derive(Clone, Debug, PartialEq)]
+        struct User(String);
+        let req = Request::builder().uri("/").header("x-user", "alice").body(HttpContext::new(std::collections::HashMap::new(), ())).unwrap();
+        let enrich = extend(|r: &Request<HttpContext<()>>| Ok(User(r.headers()["x-user"].to_str().unwrap().to_string())));
+        let req = (Ok(req) & enrich & require_extension::<User, ()>()).unwrap();
+        assert_eq!(req.body().extension::<User>(), Some(&User("alice".to_string())));
+        let req = Request::builder().uri("/").body(HttpContext::new(std::collections::HashMap::new(), ())).unwrap();
+        assert_eq!((Ok(req) & require_extension::<User, ()>()).unwrap_err().status(), 500);
+    }
+    #[test]
+    fn context_pipeline() {
+        let resources = Resources::new().with(3_i32);
+        let pipeline = context(resources, |r: Resources, v: i32| Ok(v * *r.get::<i32>().unwrap()));
+        testing::test_ok::<_,_,()>(10, 30) ^ pipeline;
+    }
+    #[test]
+    fn http_with_resource() {
+        use http_server::*;
+        let mut context = HttpContext::new(std::collections::HashMap::new(), ());
+        context.insert_extension(Resources::new().with("db".to_string()));
+        let req = Request::builder().uri("/").body(context).unwrap();
+        let handler = with_resource(|db: std::sync::Arc<String>, _: Request<HttpContext<()>>| Ok(db.to_string()));
+        assert_eq!((Ok(req) & handler).unwrap(), "db");
+    }
+    #[test]
+    fn named_pipeline() {
+        let pipeline  = filter(|v| v % 2 == 0, 1).named("even") & pipeline(|v| Ok(v / 2)).named("half");
+        testing::test_ok(10, 5) ^ pipeline.clone();
+        testing::test_error(9, 1) ^ pipeline;
+    }
+    #[test]
+    fn metrics_render() {
+        let metrics = metrics::Metrics::new();
+        metrics.increment("requests_total", &[("route", "users")]);
+        metrics.increment("requests_total", &[("route", "users")]);
+        metrics.observe("latency_seconds", &[("route", "users")], 0.02);
+        let text = metrics.render();
+        assert!(text.contains("# TYPE requests_total counter\nrequests_total{route=\"users\"} 2\n"));
+        assert!(text.contains("latency_seconds_bucket{route=\"users\",le=\"0.01\"} 0\n"));
+        assert!(text.contains("latency_seconds_bucket{route=\"users\",le=\"0.025\"} 1\n"));
+        assert!(text.contains("latency_seconds_count{route=\"users\"} 1\n"));
+        let named = pipeline(|v: i32| if v > 0 { Ok(v) } else { Err(()) }).named("metrics_render_stage");
+        let _ = named.run(1);
+        let _ = named.run(-1);
+        let labels = [("pipeline", "metrics_render_stage")];
+        assert_eq!(metrics::global().counter("lopin_pipeline_runs_total", &labels).get(), 2);
+        assert_eq!(metrics::global().counter("lopin_pipeline_errors_total", &labels).get(), 1);
+        assert_eq!(metrics::global().histogram("lopin_pipeline_duration_seconds", &labels).count(), 2);
+    }
+    #[tokio::test]
+    async fn metrics_routes() {
+        use http_server::*;
+        use http_body_util::{BodyExt, Full};
+        use hyper::{body::Bytes, Response};
+        let handler = async_pipeline(|r: Request<HttpContext<()>>| async move {
+            match r.uri().path() {
+                "/missing" => Err(Response::builder().status(404).body(Full::new(Bytes::new())).unwrap()),
+                _ => Ok(Response::new(Full::new(Bytes::from("ok")))),
+            }
+        });
+        let routed = metrics::route("metrics \"routes\"", handler);
+        assert_eq!(routed.node().metadata.get("metrics.route").map(String::as_str), Some("metrics \"routes\""));
+        let request = |uri: &str| Request::builder().uri(uri).body(HttpContext::new(std::collections::HashMap::new(), ())).unwrap();
+        assert_eq!((Ok(request("/")) & routed.clone()).await.unwrap().status(), 200);
+        assert_eq!((Ok(request("/")) & routed.clone()).await.unwrap().status(), 200);
+        assert_eq!((Ok(request("/missing")) & routed.clone()).await.unwrap_err().status(), 404);
+
+        let ok = [("route", "metrics \"routes\""), ("method", "GET"), ("status", "200")];
+        let missing = [("route", "metrics \"routes\""), ("method", "GET"), ("status", "404")];
+        assert_eq!(metrics::global().counter("lopin_http_requests_total", &ok).get(), 2);
+        assert_eq!(metrics::global().counter("lopin_http_requests_total", &missing).get(), 1);
+        assert_eq!(metrics::global().counter("lopin_http_errors_total", &missing).get(), 1);
+        assert_eq!(metrics::global().histogram("lopin_http_request_duration_seconds", &[("route", "metrics \"routes\""), ("method", "GET")]).count(), 3);
+
+        let res = (Ok(request("/metrics")) & metrics::metrics_endpoint()).unwrap();
+        assert_eq!(res.headers()["content-type"], "text/plain; version=0.0.4");
+        let text = String::from_utf8(res.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap();
+        assert!(text.contains("lopin_http_requests_total{route=\"metrics \\\"routes\\\"\",method=\"GET\",status=\"200\"} 2\n"), "{text}");
+        assert!(text.contains("lopin_http_errors_total{route=\"metrics \\\"routes\\\"\",method=\"GET\",status=\"404\"} 1\n"), "{text}");
+    }
+    #[tokio::test]
+    async fn readiness_report() {
+        use http_server::*;
+        let checks = vec![
+            ("store", async_pipeline(|_: ()| async { Ok::<(), String>(()) })),
+            ("queue", async_pipeline(|_: ()| async { Err::<(), String>("unreachable".to_string()) })),
+        ];
+        let shutdown = Shutdown::new();
+        let request = |shutdown: &Shutdown| {
+            let mut context = HttpContext::new(std::collections::HashMap::new(), ());
+            context.insert_extension(shutdown.clone());
+            Request::builder().uri("/ready").body(context).unwrap()
+        };
+        let res = (Ok(request(&shutdown)) & health::readiness(checks.clone())).await.unwrap();
+        assert_eq!(res.status(), 503);
+        let res = (Ok(request(&shutdown)) & health::readiness(checks[..1].to_vec())).await.unwrap();
+        assert_eq!(res.status(), 200);
+        let slow = |ms: u64| async_pipeline(move |_: ()| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(ms)).await;
+            Ok::<(), String>(())
+        });
+        let started = std::time::Instant::now();
+        let res = (Ok(request(&shutdown)) & health::readiness_with_timeout(vec![("a", slow(40)), ("b", slow(40)), ("c", slow(1000))], std::time::Duration::from_millis(100))).await.unwrap();
+        assert_eq!(res.status(), 503);
+        assert!(started.elapsed() < std::time::Duration::from_millis(500));
+        let report: serde_json::Value = serde_json::from_slice(&http_body_util::BodyExt::collect(res.into_body()).await.unwrap().to_bytes()).unwrap();
+        assert_eq!(report["checks"]["a"]["status"], "ok");
+        assert_eq!(report["checks"]["c"]["error"], "timed out after 100ms");
+        shutdown.trigger();
+        let res = (Ok(request(&shutdown)) & health::readiness(checks[..1].to_vec())).await.unwrap();
+        assert_eq!(res.status(), 503);
+    }
+
+    async fn connect(address: std::net::SocketAddr) -> tokio::net::TcpStream {
+        loop {
+            if let Ok(stream) = tokio::net::TcpStream::connect(address).await {
+                return stream;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+    }
+
+    async fn send_get(stream: &mut tokio::net::TcpStream, path: &str) -> (u16, String) {
+        send_raw(stream, &format!("GET {path} HTTP/1.1\r\nhost: localhost\r\n\r\n")).await
+    }
+
+    async fn send_raw(stream: &mut tokio::net::TcpStream, request: &str) -> (u16, String) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = vec![];
+        let mut buf = [0; 1024];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            assert!(n > 0, "connection closed before the response was complete");
+            response.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&response).to_string();
+            if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                let length = head.lines().find_map(|l| l.to_lowercase().strip_prefix("content-length: ").map(|v| v.parse::<usize>().unwrap())).unwrap_or(0);
+                if body.len() >= length {
+                    return (head[9..12].parse().unwrap(), body.to_string());
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn http_server_shutdown() {
+        use http_server::*;
+        use std::time::Duration;
+        let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = HttpServer::new(&address.to_string()).shutdown(shutdown.clone()).drain_delay(Duration::from_millis(300)).build();
+        let served = tokio::spawn(server ^ health::readiness(vec![]));
+
+        let mut kept_alive = connect(address).await;
+        assert_eq!(send_get(&mut kept_alive, "/ready").await.0, 200);
+        let mut probe = connect(address).await;
+        assert_eq!(tokio::time::timeout(Duration::from_secs(1), send_get(&mut probe, "/ready")).await.unwrap().0, 200);
+
+        shutdown.trigger();
+        assert_eq!(send_get(&mut kept_alive, "/ready").await.0, 503);
+        assert_eq!(send_get(&mut connect(address).await, "/ready").await.0, 503);
+        tokio::time::timeout(Duration::from_secs(2), served).await.unwrap().unwrap();
+        assert!(tokio::net::TcpStream::connect(address).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn http_server_body_limit() {
+        use http_server::*;
+        let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = HttpServer::new(&address.to_string()).shutdown(shutdown.clone()).max_body_size(8).build();
+        let served = tokio::spawn(server ^ pipeline(|r: Request<HttpContext<hyper::body::Bytes>>| {
+            Ok::<_, HttpResponse>(hyper::Response::new(http_body_util::Full::new(r.into_body().body)))
+        }));
+        let post = |body: &str| format!("POST / HTTP/1.1\r\nhost: localhost\r\ncontent-length: {}\r\n\r\n{body}", body.len());
+        assert_eq!(send_raw(&mut connect(address).await, &post("small")).await, (200, "small".to_string()));
+        assert_eq!(send_raw(&mut connect(address).await, &post("far too large")).await.0, 413);
+        shutdown.trigger();
+        served.await.unwrap();
+    }
+
+    type Fields = std::collections::BTreeMap<String, String>;
+
+    #[derive(Clone, Default)]
+    struct Captured(std::sync::Arc<std::sync::Mutex<Vec<(String, Fields)>>>);
+
+    struct FieldsVisitor<'a>(&'a mut Fields);
+
+    impl tracing::field::Visit for FieldsVisitor<'_> {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+            self.0.insert(field.name().to_string(), format!("{value:?}"));
+        }
+    }
+
+    impl tracing::Subscriber for Captured {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let mut fields = Fields::new();
+            span.record(&mut FieldsVisitor(&mut fields));
+            let mut captured = self.0.lock().unwrap();
+            captured.push((format!("span:{}", span.metadata().name()), fields));
+            tracing::span::Id::from_u64(captured.len() as u64)
+        }
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut fields = Fields::new();
+            event.record(&mut FieldsVisitor(&mut fields));
+            self.0.lock().unwrap().push(("event".to_string(), fields));
+        }
+        fn enter(&self, _: &tracing::span::Id) {}
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[tokio::test]
+    async fn access_log_records() {
+        use http_server::*;
+        let captured = Captured::default();
+        let _guard = tracing::subscriber::set_default(captured.clone());
+        let handler = async_pipeline(|r: Request<HttpContext<hyper::body::Bytes>>| async move {
+            let status = if r.uri().path() == "/missing" { 404 } else { 201 };
+            let response = hyper::Response::builder().status(status).body(http_body_util::Full::new(hyper::body::Bytes::from("done"))).unwrap();
+            if status == 404 { Err(response) } else { Ok(response) }
+        }).named("handler");
+        let logged = access_log(handler.clone());
+        assert_eq!(logged.node().kind, "access_log");
+        assert_eq!(logged.node().children, vec![handler.node()]);
+
+        let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let served = tokio::spawn(HttpServer::new(&address.to_string()).shutdown(shutdown.clone()).build() ^ logged);
+        let mut stream = connect(address).await;
+        assert_eq!(send_get(&mut stream, "/users/1").await, (201, "done".to_string()));
+        assert_eq!(send_get(&mut stream, "/missing").await, (404, "done".to_string()));
+        shutdown.trigger();
+        served.await.unwrap();
+
+        let captured = captured.0.lock().unwrap().clone();
+        let access: Vec<_> = captured.iter().filter(|(kind, fields)| kind == "event" && fields.get("message").map(String::as_str) == Some("access")).map(|(_, f)| f).collect();
+        assert_eq!(access.len(), 2);
+        assert_eq!((access[0]["method"].as_str(), access[0]["path"].as_str(), access[0]["status"].as_str()), ("GET", "/users/1", "201"));
+        assert_eq!((access[1]["path"].as_str(), access[1]["status"].as_str()), ("/missing", "404"));
+        assert!(access[0]["latency_ms"].parse::<f64>().unwrap() >= 0.0);
+        assert_eq!(access[0]["remote_addr"], format!("Some({})", stream.local_addr().unwrap()));
+        let spans: Vec<_> = captured.iter().filter(|(kind, _)| kind == "span:request").map(|(_, f)| f).collect();
+        assert_eq!(spans.len(), 2);
+        assert_eq!((spans[0]["connection"].as_str(), spans[0]["method"].as_str(), spans[0]["path"].as_str()), ("0", "GET", "/users/1"));
+    }
+
+    #[tokio::test]
+    async fn http_server_connection_info() {
+        use http_server::*;
+        let address = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let shutdown = Shutdown::new();
+        let server = HttpServer::new(&address.to_string()).shutdown(shutdown.clone()).build();
+        let served = tokio::spawn(server ^ pipeline(|r: Request<HttpContext<hyper::body::Bytes>>| {
+            let context = r.body();
+            let body = format!("{:?} {:?} {:?} {}", context.connection_id(), context.remote_addr(), context.local_addr(), context.tls().is_some());
+            Ok::<_, HttpResponse>(hyper::Response::new(http_body_util::Full::new(hyper::body::Bytes::from(body))))
+        }));
+
+        let mut first = connect(address).await;
+        let (status, body) = send_get(&mut first, "/").await;
+        assert_eq!(status, 200);
+        assert_eq!(body, format!("Some(0) Some({}) Some({}) false", first.local_addr().unwrap(), address));
+        assert_eq!(send_get(&mut first, "/").await.1, format!("Some(0) Some({}) Some({}) false", first.local_addr().unwrap(), address));
+        let mut second = connect(address).await;
+        assert_eq!(send_get(&mut second, "/").await.1, format!("Some(1) Some({}) Some({}) false", second.local_addr().unwrap(), address));
+        shutdown.trigger();
+        served.await.unwrap();
+    }
+    #[test]
+    fn pipeline_graph() {
+        let even = filter(|v: &i32| v % 2 == 0, 1).named("even") & pipeline(|v| Ok(v / 2)) & pipeline(|v| Ok(v + 1)).meta("op", "inc");
+        let pipeline = even | pipeline(Ok).named("fallback");
+        let node = pipeline.node();
+        assert_eq!(node.kind, "or");
+        assert_eq!(node.children.len(), 2);
+        assert_eq!(node.children[0].kind, "and");
+        assert_eq!(node.children[0].children.len(), 3);
+        assert_eq!(node.children[0].children[0].name.as_deref(), Some("even"));
+        assert_eq!(node.children[0].children[2].metadata.get("op").map(|v| v.as_str()), Some("inc"));
+        assert_eq!(node.children[1].label(), "fallback");
+        let dot = node.to_dot();
+        assert!(dot.starts_with("digraph pipeline {"));
+        assert!(dot.contains("n0 -> n1"));
+        assert!(node.to_json().contains("\"fallback\""));
+        let escaped = crate::graph::PipelineNode::leaf("path").with_metadata("regex", "\\d+\"x\"\nend").to_dot();
+        assert!(escaped.contains(r#"[label="path\nregex=\\d+\"x\"\nend", shape=box]"#));
+    }
+    #[test]
+    fn openapi_document() {
+        use http_server::*;
+        use openapi::*;
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct User {
+            name: String,
+        }
+        let get_user = http_get::<()>() & from_path("/users/:id") & Operation::new().summary("Get user").response::<User>(200).empty_response(404).pipeline(pipeline(|_: Request<HttpContext<()>>| http_ok(200, "{}")).meta("handler", "get_user") & set_header("x", "y"));
+        let post_user = http_post::<()>() & from_path("/users") & Operation::new().request::<User>().pipeline(pipeline(|_: Request<HttpContext<()>>| http_ok(201, "")));
+        let routes = (get_user.meta("a", "b") & pipeline(Ok)) | post_user;
+        let doc = document("users", "1.0", &routes.node());
+        let get = &doc["paths"]["/users/{id}"]["get"];
+        assert_eq!(get["summary"], "Get user");
+        assert_eq!(get["parameters"][0]["name"], "id");
+        assert_eq!(get["responses"]["200"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/User");
+        assert_eq!(get["responses"]["404"]["description"], "Not Found");
+        assert_eq!(doc["paths"]["/users"]["post"]["requestBody"]["content"]["application/json"]["schema"]["$ref"], "#/components/schemas/User");
+        assert_eq!(doc["components"]["schemas"]["User"]["type"], "object");
+        assert!(!routes.node().to_dot().contains("definitions"));
+
+        #[derive(schemars::JsonSchema)]
+        #[allow(dead_code)]
+        struct Paging {
+            page: u32,
+            size: Option<u32>,
+        }
+        let list = http_get::<()>() & from_path("/posts/:page<int>?") & Operation::new().query::<Paging>().pipeline(pipeline(|_: Request<HttpContext<()>>| http_ok(200, "")));
+        let doc = document("posts", "1.0", &list.node());
+        assert_eq!(doc["paths"]["/posts/{page}"]["get"]["parameters"][0]["in"], "path");
+        let params = doc["paths"]["/posts"]["get"]["parameters"].as_array().unwrap();
+        assert!(params.iter().all(|p| p["in"] == "query"));
+        assert!(params.iter().any(|p| p["name"] == "page" && p["required"] == true));
+        assert!(params.iter().any(|p| p["name"] == "size" && p["required"] == false));
+    }
+    #[test]
+    fn validate_body() {
+        use http_server::*;
+        use validate::*;
+        #[derive(Debug)]
+        struct User {
+            name: String,
+            age: u32,
+            email: String,
+        }
+        impl Validate for User {
+            fn validate(&self, v: &mut Validator) {
+                v.field("name", &self.name).length(1, 8);
+                v.field("age", &self.age).range(0, 150);
+                v.field("email", &self.email).matches(&regex::Regex::new("^[^@]+@[^@]+$").unwrap());
+            }
+        }
+        let user = |name: &str, age, email: &str| {
+            let body = User { name: name.to_string(), age, email: email.to_string() };
+            Request::builder().uri("/").body(HttpContext::new(std::collections::HashMap::new(), body)).unwrap()
+        };
+        assert!((Ok(user("alice", 20, "a@example.com")) & validate()).is_ok());
+        let res = (Ok(user("", 200, "a@example.com")) & validate()).unwrap_err();
+        assert_eq!(res.status(), 422);
+        let errors = check(&User { name: "".to_string(), age: 200, email: "x".to_string() }).unwrap_err();
+        let fields: Vec<&str> = errors.errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, vec!["name", "age", "email"]);
+    }
+    #[test]
+    fn problem_responses() {
+        use http_body_util::BodyExt;
+        use http_server::*;
+        use problem::*;
+        fn read_body(b: http_body_util::Full<hyper::body::Bytes>) -> Vec<u8> {
+            tokio::runtime::Builder::new_current_thread().build().unwrap().block_on(b.collect()).unwrap().to_bytes().to_vec()
+        }
+        let body = |res: HttpResponse| serde_json::from_slice::<Problem>(&read_body(res.into_body())).unwrap();
+        let req = Request::builder().uri("/other").body(HttpContext::new(std::collections::HashMap::new(), ())).unwrap();
+        let res = (Ok(req) & from_path("/users/:id")).unwrap_err();
+        assert_eq!(res.headers()["content-type"], "application/problem+json");
+        assert_eq!(body(res), Problem::new(404));
+        let parse = error_response(json::from_json::<i32>());
+        let problem = body((Ok("x".to_string()) & parse).unwrap_err());
+        assert_eq!(problem.status, 400);
+        assert_eq!(problem.title, "Bad Request");
+        assert!(problem.detail.is_some());
+        let invalid = validate::ValidationErrors { errors: vec![validate::ValidationError { field: "name".to_string(), code: "length".to_string(), message: "too long".to_string() }] };
+        let problem = body(invalid.into_response());
+        assert_eq!(problem.status, 422);
+        assert_eq!(problem.extensions["errors"][0]["field"], "name");
+        let res = Problem::new(42).into_response();
+        assert_eq!(res.status(), 500);
+        assert_eq!(body(res).status, 500);
+        assert_eq!(serde_json::Error::io(std::io::Error::other("boom")).into_response().status(), 500);
+    }
+    #[test]
+    fn negotiate_response() {
+        use http_server::*;
+        use negotiate::*;
+        #[derive(serde::Serialize)]
+        struct Item {
+            name: String,
+        }
+        let handler = negotiate(pipeline(|_: Request<HttpContext<()>>| Ok(Item { name: "a b".to_string() })));
+        let run = |accept: Option<&str>| {
+            let mut req = Request::builder().uri("/");
+            if let Some(accept) = accept {
+                req = req.header("accept", accept);
+            }
+            match Ok(req.body(HttpContext::new(std::collections::HashMap::new(), ())).unwrap()) & handler.clone() {
+                Ok(res) | Err(res) => (res.status().as_u16(), res.headers().get("content-type").map(|v| v.to_str().unwrap().to_string())),
+            }
+        };
+        assert_eq!(run(None), (200, Some("application/json".to_string())));
+        assert_eq!(run(Some("text/html;q=0.9, application/x-www-form-urlencoded")), (200, Some("application/x-www-form-urlencoded".to_string())));
+        assert_eq!(run(Some("application/json;q=0.5, text/*")), (200, Some("text/plain".to_string())));
+        assert_eq!(run(Some("image/png")), (406, Some("application/problem+json".to_string())));
+        assert_eq!(run(Some("application/json;q=0")), (406, Some("application/problem+json".to_string())));
+        assert_eq!(run(Some("application/json;q=0, */*;q=0.5")), (200, Some("application/x-www-form-urlencoded".to_string())));
+        let csv = Negotiator::new().format("text/csv", |v| Ok(v["name"].as_str().unwrap_or_default().to_string().into()));
+        assert_eq!(csv.media_type(Some("*/*")), Some("text/csv"));
+    }
+    #[test]
+    fn form_bodies() {
+        use form::*;
+        use http_server::*;
+        use hyper::body::Bytes;
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Fields {
+            title: String,
+            count: u32,
+        }
+        let request = |content_type: &str, body: &'static str| Request::builder().uri("/").header("content-type", content_type)
+            .body(HttpContext::new(std::collections::HashMap::new(), Bytes::from(body))).unwrap();
+        let req = (Ok(request("application/x-www-form-urlencoded", "title=a+b&count=3")) & form::<Fields>()).unwrap();
+        assert_eq!(req.body().body, Fields { title: "a b".to_string(), count: 3 });
+        assert_eq!((Ok(request("text/plain", "")) & form::<Fields>()).unwrap_err().status(), 415);
+
+        let body = "--xyz\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nhello\r\n--xyz\r\nContent-Disposition: form-data; name=\"count\"\r\n\r\n2\r\n--xyz\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\nContent-Type: text/plain\r\n\r\nfile\r\ndata\r\n--xyz--\r\n";
+        let req = (Ok(request("multipart/form-data; boundary=xyz", body)) & multipart()).unwrap();
+        let multipart = &req.body().body;
+        assert_eq!(multipart.field("title"), Some("hello"));
+        assert_eq!(multipart.file("file").unwrap().data, Bytes::from("file\r\ndata"));
+        assert_eq!(multipart.file("file").unwrap().filename.as_deref(), Some("a.txt"));
+        assert_eq!(multipart.fields_as::<Fields>().unwrap(), Fields { title: "hello".to_string(), count: 2 });
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Tags {
+            tag: Vec<String>,
+        }
+        let tags = "--xyz\r\nContent-Disposition: form-data; name=\"tag\"\r\n\r\na\r\n--xyz\r\nContent-Disposition: form-data; name=\"tag\"\r\n\r\nb\r\n--xyz--\r\n";
+        let req = (Ok(request("multipart/form-data; boundary=xyz", tags)) & form::multipart()).unwrap();
+        assert_eq!(req.body().body.fields_as::<Tags>().unwrap(), Tags { tag: vec!["a".to_string(), "b".to_string()] });
+        assert_eq!(req.body().body.fields_as::<Fields>().unwrap_err().status, 400);
+        let limits = MultipartLimits { max_parts: 100, max_part_size: 4 };
+        assert_eq!((Ok(request("multipart/form-data; boundary=xyz", body)) & multipart_with(limits)).unwrap_err().status(), 413);
+        let limits = MultipartLimits { max_parts: 2, max_part_size: 1024 };
+        let mut oversized = request("multipart/form-data; boundary=xyz", tags);
+        oversized.headers_mut().insert("content-length", "4096".parse().unwrap());
+        assert_eq!((Ok(oversized) & multipart_with(limits)).unwrap_err().status(), 413);
+    }
+    #[test]
+    fn multi_value_params() {
+        use http_server::*;
+        #[derive(serde::Deserialize, Clone, Debug, PartialEq)]
+        struct Search {
+            tag: Vec<String>,
+            page: u32,
+            sort: Option<String>,
+        }
+        #[derive(serde::Deserialize, Clone, Debug, PartialEq)]
+        struct Headers {
+            #[serde(rename = "x-tenant")]
+            tenant: String,
+            accept: Vec<String>,
+        }
+        let req = Request::builder().uri("/items?tag=a&tag=b&page=2").header("x-tenant", "acme").header("accept", "text/plain").header("accept", "application/json")
+            .body(HttpContext::new(std::collections::HashMap::new(), ())).unwrap();
+        let req = (Ok(req) & from_query() & from_header("X-Tenant") & params_as::<Search, ()>() & headers_as::<Headers, ()>()).unwrap();
+        assert_eq!(req.body().param_values("tag"), ["a", "b"]);
+        assert_eq!(req.body().params.get("tag"), Some(&"b".to_string()));
+        assert_eq!(req.body().params.get("x-tenant"), Some(&"acme".to_string()));
+        assert_eq!(req.body().extension::<Search>(), Some(&Search { tag: vec!["a".to_string(), "b".to_string()], page: 2, sort: None }));
+        assert_eq!(req.body().extension::<Headers>().unwrap().accept.len(), 2);
+        let mut context = req.into_body();
+        context.params.insert("tag".to_string(), "c".to_string());
+        context.params.remove("page");
+        assert_eq!(context.param_values("tag"), ["c"]);
+        assert!(context.param_values("page").is_empty());
+        let request = |uri: &str| Request::builder().uri(uri).body(HttpContext::new(std::collections::HashMap::new(), ())).unwrap();
+        assert!((Ok(request("/items?tag=a&page=2")) & from_query() & params_as::<Search, ()>()).is_ok());
+        assert_eq!((Ok(request("/items?tag=a&page=x")) & from_query() & params_as::<Search, ()>()).unwrap_err().status(), 400);
+        assert_eq!((Ok(request("/items?tag=a&page=2")) & from_query() & from_header("x-tenant")).unwrap_err().status(), 400);
+    }
+    #[test]
+    fn path_patterns() {
+        use http_server::*;
+        let request = |uri: &str| Request::builder().uri(uri).body(HttpContext::new(std::collections::HashMap::new(), ())).unwrap();
+        let param = |p: Pipeline<Request<HttpContext<()>>, Request<HttpContext<()>>, HttpResponse>, uri: &str, key: &str| {
+            (Ok(request(uri)) & p).ok().map(|r| r.body().params.get(key).cloned())
+        };
+        assert_eq!(param(from_path("/users/:id<int>"), "/users/42", "id"), Some(Some("42".to_string())));
+        assert_eq!(param(from_path("/users/:id<int>"), "/users/abc", "id"), None);
+        assert_eq!(param(from_path("/files/*rest"), "/files/a/b%20c.txt", "rest"), Some(Some("a/b c.txt".to_string())));
+        assert_eq!(param(from_path("/posts/:page?"), "/posts", "page"), Some(None));
+        assert_eq!(param(from_path("/posts/:page?"), "/posts/3", "page"), Some(Some("3".to_string())));
+        assert_eq!(param(from_path("/v1.0/:code<[A-Z]{3}>"), "/v1x0/ABC", "code"), None);
+        assert_eq!(param(from_path("/v1.0/:code<[A-Z]{3}>"), "/v1.0/ABC", "code"), Some(Some("ABC".to_string())));
+        assert_eq!(param(from_path("/files/:name.json"), "/files/a.json", "name"), Some(Some("a".to_string())));
+        assert_eq!(param(from_path("/files/:name.json"), "/files/a.xml", "name"), None);
+        assert_eq!(param(from_path("/items/:id<int>.json"), "/items/7.json", "id"), Some(Some("7".to_string())));
+        assert_eq!(param(from_path("/items/:item-id"), "/items/5-id", "item"), Some(Some("5".to_string())));
+        assert!(std::panic::catch_unwind(|| from_path::<()>("/items/:-id")).is_err());
+
+        let api = nest("/api", from_path("/users/:id") & pipeline(|r: Request<HttpContext<()>>| Ok(format!("{} {}", r.uri(), r.body().params["id"]))));
+        assert_eq!((Ok(request("/api/users/7?x=1")) & api.clone()).unwrap(), "/users/7?x=1 7");
+        assert_eq!((Ok(request("/apix/users/7")) & api.clone()).unwrap_err().status(), 404);
+        let doc = openapi::document("api", "1", &api.node());
+        assert!(doc["paths"]["/api/users/{id}"]["get"].is_object());
+    }
+
+    #[tokio::test]
+    async fn join_and_race() {
+        use std::time::Duration;
+        let slow = |v: i32, ms: u64| async_pipeline(move |x: i32| async move {
+            tokio::time::sleep(Duration::from_millis(ms)).await;
+            Ok::<i32, String>(x + v)
+        });
+        let failing = async_pipeline(|_: i32| async { Err::<i32, String>("failed".to_string()) });
+        assert_eq!((Ok(1) & join(slow(1, 20), slow(2, 10))).await, Ok((2, 3)));
+        assert_eq!((Ok(1) & join_all(vec![slow(1, 10), slow(2, 0), slow(3, 5)])).await, Ok(vec![2, 3, 4]));
+        assert_eq!((Ok(1) & join(slow(1, 0), failing.clone())).await, Err("failed".to_string()));
+        assert_eq!((Ok(1) & race(slow(1, 50), slow(2, 0))).await, Ok(3));
+        assert_eq!((Ok(1) & race(failing.clone(), slow(2, 10))).await, Ok(3));
+        assert_eq!((Ok(1) & race(failing.clone(), failing)).await, Err("failed".to_string()));
+        let nested = join(join(slow(1, 0), slow(2, 0)), slow(3, 0)).node();
+        assert_eq!((nested.children.len(), nested.children[0].kind.as_str()), (2, "join"));
+    }
+
+    #[tokio::test]
+    async fn each_pipelines() {
+        let parse = pipeline(|s: &str| s.parse::<i32>().map_err(|_| s.to_string()));
+        assert_eq!(each(parse.clone()).run(vec!["1", "2", "3"]), Ok(vec![1, 2, 3]));
+        assert_eq!(each(parse.clone()).run(vec!["1", "x", "y"]), Err("x".to_string()));
+        assert_eq!(each_collect(parse.clone()).run(vec!["1", "x", "y"]), Err(vec!["x".to_string(), "y".to_string()]));
+        assert_eq!(each(each(parse.clone())).node().children[0].kind, "each");
+
+        let slow = async_pipeline(|v: u64| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(10 * (4 - v))).await;
+            if v == 0 { Err(v) } else { Ok(v * 2) }
+        });
+        assert_eq!((Ok(vec![1, 2, 3]) & async_each(slow.clone(), 2)).await, Ok(vec![2, 4, 6]));
+        assert_eq!((Ok(vec![1, 0, 3, 0]) & async_each(slow.clone(), 4)).await, Err(0));
+        assert_eq!((Ok(vec![1, 0, 3, 0]) & async_each_collect(slow.clone(), 4)).await, Err(vec![0, 0]));
+        let input: futures::stream::BoxStream<'static, u64> = Box::pin(futures::stream::iter(vec![3, 2, 1]));
+        assert_eq!((Ok(input) & stream_each(slow, 0)).await, Ok(vec![6, 4, 2]));
+    }
+
+    #[tokio::test]
+    async fn stream_framework_pipeline() {
+        use crate::stream::{channel, lines, stream_framework, ErrorPolicy};
+        use std::sync::{Arc, Mutex};
+
+        let collected = Arc::new(Mutex::new(vec![]));
+        let store = collected.clone();
+        let sink = async_pipeline(move |v: i32| {
+            let store = store.clone();
+            async move {
+                store.lock().unwrap().push(v);
+                Ok::<(), String>(())
+            }
+        });
+        let double = async_pipeline(|v: i32| async move {
+            if v < 0 { Err(format!("negative {}", v)) } else { Ok(v * 2) }
+        });
+
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        for v in [1, -1, 2, 3] {
+            tx.send(v).await.unwrap();
+        }
+        drop(tx);
+        let failures = Arc::new(Mutex::new(vec![]));
+        let errors = failures.clone();
+        let error_sink = async_pipeline(move |e: String| {
+            let errors = errors.clone();
+            async move {
+                errors.lock().unwrap().push(e);
+                Ok::<(), String>(())
+            }
+        });
+        (double.clone() ^ stream_framework(channel(rx)).sink(sink.clone()).error_sink(error_sink).concurrency(2).build()).await;
+        assert_eq!(*collected.lock().unwrap(), vec![2, 4, 6]);
+        assert_eq!(*failures.lock().unwrap(), vec!["negative -1".to_string()]);
+
+        collected.lock().unwrap().clear();
+        let input: &'static [u8] = b"1\n2\n-3\n4\n";
+        let parse = async_pipeline(|s: String| async move { s.parse::<i32>().map_err(|e| e.to_string()) });
+        (parse & double ^ stream_framework(lines(input)).sink(sink).on_error(ErrorPolicy::Stop).build()).await;
+        assert_eq!(*collected.lock().unwrap(), vec![2, 4]);
+    }
+
+    #[tokio::test]
+    async fn stdio_lines_processing() {
+        let upper = async_pipeline(|s: String| async move {
+            if s.is_empty() { Err("empty line".to_string()) } else { Ok(s.to_uppercase()) }
+        });
+        let input: &'static [u8] = b"foo\n\nbar\n";
+        let mut output = vec![];
+        let mut errors = vec![];
+        let failures = crate::cli::process_lines(&upper, input, &mut output, &mut errors).await.unwrap();
+        assert_eq!(failures, 1);
+        assert_eq!(String::from_utf8(output).unwrap(), "FOO\nBAR\n");
+        assert_eq!(String::from_utf8(errors).unwrap(), "empty line\n");
+
+        let (mut output, mut errors) = (vec![], vec![]);
+        assert_eq!(crate::cli::run_lines(&upper, &b"foo\n"[..], &mut output, &mut errors).await, 0);
+        assert_eq!(crate::cli::run_lines(&upper, &b"\n"[..], &mut output, &mut errors).await, 1);
+    }
+
+    #[test]
+    fn args_parsing() {
+        use crate::cli::{parse_args, run_args};
+
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Args {
+            name: String,
+            count: Option<u32>,
+            #[serde(default)]
+            dry_run: bool,
+            #[serde(default)]
+            tag: Vec<String>,
+            #[serde(default)]
+            args: Vec<String>,
+        }
+
+        let args = |v: &[&str]| v.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(parse_args::<Args, _>(args(&["--name", "a", "--count=3", "--tag", "x", "--tag=y", "file", "--dry-run", "--", "--raw"])), Ok(Args {
+            name: "a".to_string(),
+            count: Some(3),
+            dry_run: true,
+            tag: vec!["x".to_string(), "y".to_string()],
+            args: vec!["file".to_string(), "--raw".to_string()],
+        }));
+        assert!(parse_args::<Args, _>(args(&["--count", "x"])).is_err());
+        let parsed = parse_args::<Args, _>(args(&["--dry-run", "file", "--name", "-", "--tag", "-5"])).unwrap();
+        assert!(parsed.dry_run);
+        assert_eq!(parsed.args, vec!["file".to_string()]);
+        assert_eq!(parsed.name, "-");
+        assert_eq!(parsed.tag, vec!["-5".to_string()]);
+        assert!(!parse_args::<Args, _>(args(&["--name=a", "--dry-run=false"])).unwrap().dry_run);
+        assert_eq!(parse_args::<Args, _>(args(&["--name"])), Err("missing value for --name".to_string()));
+        assert_eq!(parse_args::<Args, _>(args(&["--name", "--dry-run"])), Err("missing value for --name".to_string()));
+
+        let greet = pipeline(|a: Args| if a.name.is_empty() { Err("empty name") } else { Ok(format!("hello {}", a.name)) });
+        let (mut output, mut errors) = (vec![], vec![]);
+        assert_eq!(run_args(&greet, args(&["--name", "lopin"]), &mut output, &mut errors), 0);
+        assert_eq!(String::from_utf8(output).unwrap(), "hello lopin\n");
+        let (mut output, mut errors) = (vec![], vec![]);
+        assert_eq!(run_args(&greet, args(&["--name="]), &mut output, &mut errors), 1);
+        assert_eq!(String::from_utf8(errors).unwrap(), "empty name\n");
+        let (mut output, mut errors) = (vec![], vec![]);
+        assert_eq!(run_args(&greet, args(&[]), &mut output, &mut errors), 2);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn command_pipelines() {
+        use crate::command::{CommandError, CommandInput, CommandSpec};
+        use std::time::{Duration, Instant};
+
+        let echo = CommandSpec::new("echo").arg("hello").pipeline();
+        let output = echo.run(CommandInput::new().arg("world")).unwrap();
+        assert!(output.success());
+        assert_eq!(output.stdout_string(), "hello world\n");
+
+        let script = CommandSpec::new("sh").args(&["-c", "cat; printf \"$GREETING\" >&2; exit ${CODE:-0}"]).env("GREETING", "hi").pipeline();
+        let output = script.run(CommandInput::new().stdin(b"piped")).unwrap();
+        assert_eq!((output.stdout_string(), output.stderr_string()), ("piped".to_string(), "hi".to_string()));
+        match script.run(CommandInput::new().env("CODE", "3")) {
+            Err(CommandError::Exit(output)) => assert_eq!((output.status, output.stderr_string()), (Some(3), "hi".to_string())),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let pwd = CommandSpec::new("pwd").current_dir("/").pipeline();
+        assert_eq!(pwd.run(CommandInput::new()).unwrap().stdout_string(), "/\n");
+        let tmp = std::env::temp_dir().canonicalize().unwrap();
+        let printed = pwd.run(CommandInput::new().dir(tmp.to_str().unwrap())).unwrap().stdout_string();
+        assert_eq!(std::path::PathBuf::from(printed.trim_end()).canonicalize().unwrap(), tmp);
+
+        let started = Instant::now();
+        let sleep = CommandSpec::new("sleep").arg("5").timeout(Duration::from_millis(50)).pipeline();
+        assert!(matches!(sleep.run(CommandInput::new()), Err(CommandError::Timeout)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let started = Instant::now();
+        let orphaned = CommandSpec::new("sh").args(&["-c", "sleep 5 & sleep 5"]).timeout(Duration::from_millis(50)).pipeline();
+        assert!(matches!(orphaned.run(CommandInput::new()), Err(CommandError::Timeout)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let marker = tmp.join(format!("lopin-kill-on-drop-{}", std::process::id()));
+        let detached = CommandSpec::new("sh").args(&["-c", "sleep 0.2; touch \"$MARKER\""]).env("MARKER", marker.to_str().unwrap()).timeout(Duration::from_millis(20)).kill_on_drop(false).pipeline();
+        assert!(matches!(detached.run(CommandInput::new()), Err(CommandError::Timeout)));
+        std::thread::sleep(Duration::from_millis(600));
+        assert!(marker.exists());
+        let _ = std::fs::remove_file(&marker);
+        assert!(matches!(CommandSpec::new("lopin-missing-program").run(CommandInput::new()), Err(CommandError::Io(_))));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn async_command_pipelines() {
+        use crate::command::{async_command, async_pipe, CommandError, CommandInput, CommandSpec};
+        use std::time::Duration;
+
+        let output = (Ok(CommandInput::new().arg("hello")) & async_command("echo")).await.unwrap();
+        assert_eq!(output.stdout_string(), "hello\n");
+
+        let chain = async_pipe(&[CommandSpec::new("printf"), CommandSpec::new("tr").args(&["a-z", "A-Z"]), CommandSpec::new("rev")]);
+        let output = (Ok(CommandInput::new().arg("abc\ndef\n")) & chain).await.unwrap();
+        assert_eq!(output.stdout_string(), "CBA\nFED\n");
+        let streaming = async_pipe(&[CommandSpec::new("yes"), CommandSpec::new("head").args(&["-n", "2"])]);
+        assert_eq!((Ok(CommandInput::new()) & streaming).await.unwrap().stdout_string(), "y\ny\n");
+        let piped = (CommandSpec::new("printf") & CommandSpec::new("tr").args(&["a-z", "A-Z"]) & CommandSpec::new("rev")).async_pipeline();
+        assert_eq!((Ok(CommandInput::new().arg("abc\n")) & piped.clone()).await.unwrap().stdout_string(), "CBA\n");
+        assert_eq!(piped.node().metadata.get("command.program").map(String::as_str), Some("printf | tr | rev"));
+        let failing = async_pipe(&[CommandSpec::new("echo").arg("in"), CommandSpec::new("sh").args(&["-c", "cat >/dev/null; echo oops >&2; exit 2"])]);
+        assert!(matches!((Ok(CommandInput::new()) & failing).await, Err(CommandError::Exit(o)) if o.status == Some(2) && o.stderr_string() == "oops\n"));
+
+        let length = async_pipeline(|line: String| async move {
+            if line == "stop" { Err(CommandError::Timeout) } else { Ok(line.len()) }
+        });
+        let lines = CommandSpec::new("printf").async_lines(length.clone());
+        assert_eq!((Ok(CommandInput::new().arg("a\nbbb\ncc\n")) & lines.clone()).await.ok(), Some(vec![1, 3, 2]));
+        assert!(matches!((Ok(CommandInput::new().arg("a\nstop\n")) & lines).await, Err(CommandError::Timeout)));
+        let failing = CommandSpec::new("sh").args(&["-c", "echo one; exit 4"]).async_lines(length);
+        assert!(matches!((Ok(CommandInput::new()) & failing).await, Err(CommandError::Exit(o)) if o.status == Some(4)));
+
+        let sleep = CommandSpec::new("sleep").arg("5").timeout(Duration::from_millis(50)).async_pipeline();
+        assert!(matches!((Ok(CommandInput::new()) & sleep).await, Err(CommandError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn blocking_pipelines() {
+        let current = std::thread::current().id();
+        let thread_id = pipeline(|_: ()| Ok::<_, ()>(std::thread::current().id()));
+        let marked = thread_id.clone().mark_blocking();
+        assert!(!thread_id.is_blocking());
+        assert!(marked.is_blocking());
+        assert!((pipeline(|v: ()| Ok::<(), ()>(v)) & marked.clone()).named("composed").is_blocking());
+        assert!((thread_id.clone() | marked.clone()).is_blocking());
+        assert!(crate::command::command("true").is_blocking());
+        assert!(problem::error_response(pipeline(|v: ()| Ok::<(), problem::Problem>(v)).mark_blocking()).is_blocking());
+        assert!((branch::guard(|_: &()| true, ()) & marked.clone()).pipeline().is_blocking());
+        assert!(futures::executor::block_on(AsyncPipeline::new(marked.clone()).async_run(())).is_ok());
+
+        assert_eq!((Ok(()) & AsyncPipeline::new(thread_id.clone())).await, Ok(current));
+        assert_ne!((Ok(()) & thread_id.clone().blocking()).await, Ok(current));
+        let composed = marked.clone() & async_pipeline(|id: std::thread::ThreadId| async move { Ok::<_, ()>(id) });
+        assert_ne!((Ok(()) & composed).await, Ok(current));
+        let passthrough = async_pipeline(|v: ()| async move { Ok::<_, ()>(v) });
+        assert_eq!((Ok(()) & passthrough.clone() & thread_id.clone()).await, Ok(current));
+        assert_ne!((Ok(()) & passthrough & marked).await, Ok(current));
+    }
+
+    #[test]
+    fn block_on_pipelines() {
+        let delayed = async_pipeline(|v: i32| async move {
+            tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            if v < 0 { Err(v) } else { Ok(v * 2) }
+        });
+        let sync = block_on(delayed.clone());
+        assert!(sync.is_blocking());
+        testing::test_ok(2, 4) ^ sync.clone();
+        testing::test_error(-1, -1) ^ sync.clone();
+        assert_eq!(each(sync).run(vec![1, 2]), Ok(vec![2, 4]));
+
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let with_handle = block_on_with(delayed.clone(), runtime.handle().clone());
+        let result = runtime.block_on(async { tokio::task::spawn_blocking(move || with_handle.run(3)).await.unwrap() });
+        assert_eq!(result, Ok(6));
+
+        runtime.block_on(async {
+            (testing::async_test_ok(2, 4) ^ delayed.clone()).await;
+            (testing::async_test_error(-1, -1) ^ delayed).await;
+            (testing::async_test_ok(1, 1) ^ filter(|_| true, 0)).await;
+        });
+    }
+
+    #[tokio::test]
+    async fn typed_composition() {
+        use crate::typed::{self, AsyncStaticPipeline, StaticPipeline};
+
+        let parse = typed::pipeline(|s: String| s.parse::<i32>().map_err(|e| e.to_string()));
+        let chain = parse & typed::pipeline(|v: i32| Ok::<_, String>(v * 2)) & typed::dynamic(pipeline(|v: i32| Ok::<_, String>(v + 1)));
+        assert_eq!(chain.run("20".to_string()), Ok(41));
+        assert!(chain.run("x".to_string()).is_err());
+
+        let fallback = typed::pipeline(|v: i32| if v > 0 { Ok(v) } else { Err("negative".to_string()) }) | typed::pipeline(|v: i32| Ok::<_, String>(-v));
+        let erased = fallback.erase();
+        assert_eq!(erased.node().kind, "or");
+        assert_eq!(erased.node().children.len(), 2);
+        let erased = erased & pipeline(|v: i32| Ok::<_, String>(v.to_string()));
+        let blocking = typed::pipeline(|v: i32| Ok::<_, String>(v)) & typed::dynamic(pipeline(|v: i32| Ok::<_, String>(v)).mark_blocking());
+        assert!(blocking.erase().is_blocking());
+        testing::test_ok(-3, "3".to_string()) ^ erased;
+
+        let fetch = typed::async_pipeline(|v: i32| async move { Ok::<_, String>(v * 10) });
+        let chain = fetch & typed::dynamic(async_pipeline(|v: i32| async move { if v > 50 { Err("too large".to_string()) } else { Ok(v) } }));
+        assert_eq!(chain.async_run(3).await, Ok(30));
+        (testing::async_test_error(6, "too large".to_string()) ^ chain.erase_async()).await;
+    }
+
+    #[tokio::test]
+    async fn branches_without_clone() {
+        use http_server::*;
+        use http_body_util::{BodyExt, Full};
+        use hyper::{body::Bytes, Method, Response};
+
+        #[derive(Debug, PartialEq)]
+        struct Upload(Vec<u8>);
+
+        let halve = guard(|v: &Upload| v.0.len() % 2 == 0, "odd") & pipeline(|v: Upload| if v.0.is_empty() { Err("empty") } else { Ok(v.0.len() / 2) });
+        let count = branch(|v: Upload| if v.0.len() > 2 { Ok(v.0.len()) } else { Err(BranchError::Rejected(v, "short")) });
+        let chain = halve.clone() | count.clone();
+        assert_eq!(chain.run(Upload(vec![0; 4])), Ok(2));
+        assert_eq!(chain.run(Upload(vec![0; 5])), Ok(5));
+        assert_eq!(chain.run(Upload(vec![])), Err(BranchError::Failed("empty")));
+        assert_eq!(chain.run(Upload(vec![0])), Err(BranchError::Rejected(Upload(vec![0]), "short")));
+        assert_eq!(chain.clone().pipeline().run(Upload(vec![0])), Err("short"));
+        assert_eq!((chain | pipeline(|v: Upload| Ok(v.0.len() + 100))).run(Upload(vec![0])), Ok(101));
+
+        let request = |method: Method, uri: &str| Request::builder().method(method).uri(uri).body(HttpContext::new(std::collections::HashMap::new(), Upload(vec![1, 2]))).unwrap();
+        let show = path_guard("/files/:name") & method_guard(Method::GET) & async_pipeline(|r: Request<HttpContext<Upload>>| async move {
+            Ok::<_, HttpResponse>(Response::new(Full::new(Bytes::from(format!("show {}", r.body().params["name"])))))
+        });
+        let create = path_guard("/files") & method_guard(Method::POST) & async_pipeline(|r: Request<HttpContext<Upload>>| async move {
+            Ok::<_, HttpResponse>(Response::new(Full::new(Bytes::from(format!("create {}", r.body().body.0.len())))))
+        });
+        let routes = show.or_with(create, most_specific).async_pipeline();
+        let body = |r: HttpResponse| async move { String::from_utf8(r.into_body().collect().await.unwrap().to_bytes().to_vec()).unwrap() };
+        assert_eq!(body((Ok(request(Method::GET, "/files/a%20b")) & routes.clone()).await.unwrap()).await, "show a b");
+        assert_eq!(body((Ok(request(Method::POST, "/files")) & routes.clone()).await.unwrap()).await, "create 2");
+        assert_eq!((Ok(request(Method::POST, "/other")) & routes.clone()).await.unwrap_err().status(), 404);
+        assert_eq!((Ok(request(Method::GET, "/other")) & routes.clone()).await.unwrap_err().status(), 404);
+        assert_eq!((Ok(request(Method::POST, "/files/a")) & routes.clone()).await.unwrap_err().status(), 405);
+        assert_eq!((Ok(request(Method::DELETE, "/files")) & routes.clone()).await.unwrap_err().status(), 405);
+        assert_eq!((Ok(request(Method::GET, "/files/%ff")) & routes.clone()).await.unwrap_err().status(), 400);
+
+        let doc = openapi::document("files", "1", &routes.node());
+        assert!(doc["paths"]["/files/{name}"]["get"].is_object());
+        assert!(doc["paths"]["/files"]["post"].is_object());
+
+        let params = |r: Request<HttpContext<Upload>>| {
+            let mut params: Vec<String> = r.body().params.iter().map(|(k, v)| format!("{k}={v}")).collect();
+            params.sort();
+            Ok::<_, HttpResponse>(params.join("&"))
+        };
+        let users = (path_guard("/users/:id") & method_guard(Method::POST) & pipeline(params))
+            | (path_guard("/users/:name") & method_guard(Method::GET) & pipeline(params))
+            | (path_guard("/users/:key") & pipeline(params));
+        assert_eq!(users.run(request(Method::GET, "/users/5")).ok(), Some("name=5".to_string()));
+        assert_eq!(users.run(request(Method::PUT, "/users/5")).ok(), Some("key=5".to_string()));
+        assert_eq!(users.run(request(Method::POST, "/users/5")).ok(), Some("id=5".to_string()));
+    }
 }
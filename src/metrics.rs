@@ -0,0 +1,219 @@
+use std::{collections::BTreeMap, fmt::Write, sync::{atomic::{AtomicU64, Ordering}, Arc, OnceLock, RwLock}, time::Instant};
+
+use http_body_util::Full;
+use hyper::{body::Bytes, header::CONTENT_TYPE, Request, Response};
+use async_trait::async_trait;
+
+use crate::{graph::PipelineNode, http_server::{HttpAsyncPipeline, HttpContext, HttpPipeline}, pipeline, util::escape_quoted, AsyncPipeline, RawAsyncPipeline};
+
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+type Labels = Vec<(String, String)>;
+
+#[derive(Clone, Default)]
+pub struct Counter {
+  value: Arc<AtomicU64>
+}
+
+impl Counter {
+  pub fn increment(&self) {
+    self.value.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn get(&self) -> u64 {
+    self.value.load(Ordering::Relaxed)
+  }
+}
+
+struct HistogramInner {
+  buckets: Vec<AtomicU64>,
+  sum: AtomicU64,
+  count: AtomicU64
+}
+
+#[derive(Clone)]
+pub struct Histogram {
+  inner: Arc<HistogramInner>
+}
+
+impl Histogram {
+  fn new() -> Histogram {
+    Histogram {
+      inner: Arc::new(HistogramInner {
+        buckets: BUCKETS.iter().map(|_| AtomicU64::new(0)).collect(),
+        sum: AtomicU64::new(0f64.to_bits()),
+        count: AtomicU64::new(0)
+      })
+    }
+  }
+
+  pub fn observe(&self, value: f64) {
+    for (i, le) in BUCKETS.iter().enumerate() {
+      if value <= *le {
+        self.inner.buckets[i].fetch_add(1, Ordering::Relaxed);
+      }
+    }
+    let _ = self.inner.sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| Some((f64::from_bits(bits) + value).to_bits()));
+    self.inner.count.fetch_add(1, Ordering::Relaxed);
+  }
+
+  pub fn count(&self) -> u64 {
+    self.inner.count.load(Ordering::Relaxed)
+  }
+}
+
+#[derive(Default)]
+struct MetricsInner {
+  counters: BTreeMap<String, BTreeMap<Labels, Counter>>,
+  histograms: BTreeMap<String, BTreeMap<Labels, Histogram>>
+}
+
+#[derive(Default)]
+pub struct Metrics {
+  inner: RwLock<MetricsInner>
+}
+
+fn to_labels(labels: &[(&str, &str)]) -> Labels {
+  labels.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+fn format_labels(labels: &Labels, extra: Option<(&str, String)>) -> String {
+  let mut parts: Vec<String> = labels.iter().map(|(k, v)| format!("{k}=\"{}\"", escape_quoted(v))).collect();
+  if let Some((k, v)) = extra {
+    parts.push(format!("{k}=\"{v}\""));
+  }
+  if parts.is_empty() {
+    String::new()
+  } else {
+    format!("{{{}}}", parts.join(","))
+  }
+}
+
+impl Metrics {
+  pub fn new() -> Metrics {
+    Metrics::default()
+  }
+
+  pub fn counter(&self, name: &str, labels: &[(&str, &str)]) -> Counter {
+    let labels = to_labels(labels);
+    if let Some(counter) = self.inner.read().unwrap().counters.get(name).and_then(|series| series.get(&labels)) {
+      return counter.clone();
+    }
+    self.inner.write().unwrap().counters.entry(name.to_string()).or_default().entry(labels).or_default().clone()
+  }
+
+  pub fn histogram(&self, name: &str, labels: &[(&str, &str)]) -> Histogram {
+    let labels = to_labels(labels);
+    if let Some(histogram) = self.inner.read().unwrap().histograms.get(name).and_then(|series| series.get(&labels)) {
+      return histogram.clone();
+    }
+    self.inner.write().unwrap().histograms.entry(name.to_string()).or_default().entry(labels).or_insert_with(Histogram::new).clone()
+  }
+
+  pub fn increment(&self, name: &str, labels: &[(&str, &str)]) {
+    self.counter(name, labels).increment();
+  }
+
+  pub fn observe(&self, name: &str, labels: &[(&str, &str)], value: f64) {
+    self.histogram(name, labels).observe(value);
+  }
+
+  pub fn render(&self) -> String {
+    let inner = self.inner.read().unwrap();
+    let mut out = String::new();
+    for (name, series) in inner.counters.iter() {
+      let _ = writeln!(out, "# TYPE {name} counter");
+      for (labels, counter) in series.iter() {
+        let _ = writeln!(out, "{name}{} {}", format_labels(labels, None), counter.get());
+      }
+    }
+    for (name, series) in inner.histograms.iter() {
+      let _ = writeln!(out, "# TYPE {name} histogram");
+      for (labels, histogram) in series.iter() {
+        for (le, count) in BUCKETS.iter().zip(histogram.inner.buckets.iter()) {
+          let _ = writeln!(out, "{name}_bucket{} {}", format_labels(labels, Some(("le", le.to_string()))), count.load(Ordering::Relaxed));
+        }
+        let _ = writeln!(out, "{name}_bucket{} {}", format_labels(labels, Some(("le", "+Inf".to_string()))), histogram.count());
+        let _ = writeln!(out, "{name}_sum{} {}", format_labels(labels, None), f64::from_bits(histogram.inner.sum.load(Ordering::Relaxed)));
+        let _ = writeln!(out, "{name}_count{} {}", format_labels(labels, None), histogram.count());
+      }
+    }
+    out
+  }
+}
+
+pub fn global() -> &'static Metrics {
+  static METRICS: OnceLock<Metrics> = OnceLock::new();
+  METRICS.get_or_init(Metrics::new)
+}
+
+pub(crate) struct PipelineMetrics {
+  runs: Counter,
+  errors: Counter,
+  duration: Histogram
+}
+
+impl PipelineMetrics {
+  pub(crate) fn new(name: &str) -> PipelineMetrics {
+    let metrics = global();
+    PipelineMetrics {
+      runs: metrics.counter("lopin_pipeline_runs_total", &[("pipeline", name)]),
+      errors: metrics.counter("lopin_pipeline_errors_total", &[("pipeline", name)]),
+      duration: metrics.histogram("lopin_pipeline_duration_seconds", &[("pipeline", name)])
+    }
+  }
+
+  pub(crate) fn record(&self, start: Instant, ok: bool) {
+    self.runs.increment();
+    if !ok {
+      self.errors.increment();
+    }
+    self.duration.observe(start.elapsed().as_secs_f64());
+  }
+}
+
+struct RoutePipeline<T> {
+  name: String,
+  inner: HttpAsyncPipeline<T>
+}
+
+#[async_trait]
+impl<T: Send + 'static> RawAsyncPipeline<Request<HttpContext<T>>, Response<Full<Bytes>>, Response<Full<Bytes>>> for RoutePipeline<T> {
+  async fn async_run(&self, r: Request<HttpContext<T>>) -> Result<Response<Full<Bytes>>, Response<Full<Bytes>>> {
+    let method = r.method().to_string();
+    let start = Instant::now();
+    let result = self.inner.async_run(r).await;
+    let status = match &result {
+      Ok(res) => res.status(),
+      Err(res) => res.status(),
+    };
+    let metrics = global();
+    metrics.increment("lopin_http_requests_total", &[("route", &self.name), ("method", &method), ("status", status.as_str())]);
+    if result.is_err() {
+      metrics.increment("lopin_http_errors_total", &[("route", &self.name), ("method", &method), ("status", status.as_str())]);
+    }
+    metrics.observe("lopin_http_request_duration_seconds", &[("route", &self.name), ("method", &method)], start.elapsed().as_secs_f64());
+    result
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("metrics", vec![self.inner.node()]).with_metadata("metrics.route", &self.name)
+  }
+}
+
+pub fn route<T: Send + 'static>(name: &str, pipeline: HttpAsyncPipeline<T>) -> HttpAsyncPipeline<T> {
+  AsyncPipeline::new(RoutePipeline {
+    name: name.to_string(),
+    inner: pipeline
+  })
+}
+
+pub fn metrics_endpoint<T: 'static>() -> HttpPipeline<T> {
+  pipeline(|_: Request<HttpContext<T>>| {
+    Ok(Response::builder()
+      .status(200)
+      .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+      .body(Full::new(Bytes::from(global().render())))
+      .unwrap())
+  })
+}
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use http_body_util::Full;
+use hyper::{body::Bytes, header::{ACCEPT, CONTENT_TYPE, VARY}, Request, Response};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{async_pipeline, http_server::{HttpAsyncPipeline, HttpContext, HttpPipeline}, pipeline, problem::{IntoResponse, Problem}, RawAsyncPipeline, RawPipeline};
+
+type Renderer = Arc<dyn Fn(&Value) -> Result<Bytes, String> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Negotiator {
+  formats: Vec<(String, Renderer)>
+}
+
+impl Default for Negotiator {
+  fn default() -> Self {
+    Negotiator::new()
+      .format("application/json", |v| serde_json::to_vec(v).map(Bytes::from).map_err(|e| e.to_string()))
+      .format("application/x-www-form-urlencoded", |v| serde_urlencoded::to_string(v).map(Bytes::from).map_err(|e| e.to_string()))
+      .format("text/plain", |v| match v {
+        Value::String(s) => Ok(Bytes::from(s.clone())),
+        v => Ok(Bytes::from(v.to_string())),
+      })
+  }
+}
+
+fn parse_accept(accept: &str) -> Vec<(String, f32)> {
+  let mut ranges: Vec<(String, f32)> = accept.split(',').filter_map(|part| {
+    let mut params = part.split(';');
+    let media_type = params.next()?.trim().to_lowercase();
+    if media_type.is_empty() {
+      return None;
+    }
+    let q = params.filter_map(|p| p.trim().strip_prefix("q=")).find_map(|q| q.trim().parse::<f32>().ok()).unwrap_or(1.0);
+    Some((media_type, q))
+  }).collect();
+  ranges.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+  ranges
+}
+
+fn matches(range: &str, media_type: &str) -> bool {
+  if range == "*/*" || range == media_type {
+    return true;
+  }
+  match (range.strip_suffix("/*"), media_type.split_once('/')) {
+    (Some(kind), Some((media_kind, _))) => kind == media_kind,
+    _ => false,
+  }
+}
+
+fn specificity(range: &str) -> u8 {
+  match range {
+    "*/*" => 0,
+    range if range.ends_with("/*") => 1,
+    _ => 2,
+  }
+}
+
+impl Negotiator {
+  pub fn new() -> Negotiator {
+    Negotiator {
+      formats: vec![]
+    }
+  }
+
+  pub fn format<F: Fn(&Value) -> Result<Bytes, String> + Send + Sync + 'static>(mut self, media_type: &str, f: F) -> Negotiator {
+    self.formats.push((media_type.to_lowercase(), Arc::new(f)));
+    self
+  }
+
+  fn select(&self, accept: Option<&str>) -> Option<&(String, Renderer)> {
+    let Some(ranges) = accept.map(parse_accept) else {
+      return self.formats.first();
+    };
+    let refused = |range: &str, media_type: &str| ranges.iter().any(|(r, q)| *q <= 0.0 && matches(r, media_type) && specificity(r) >= specificity(range));
+    ranges.iter().filter(|(_, q)| *q > 0.0).find_map(|(range, _)| {
+      self.formats.iter().find(|(media_type, _)| matches(range, media_type) && !refused(range, media_type))
+    })
+  }
+
+  pub fn media_type(&self, accept: Option<&str>) -> Option<&str> {
+    self.select(accept).map(|(media_type, _)| media_type.as_str())
+  }
+
+  pub fn render<T: Serialize>(&self, accept: Option<&str>, value: &T) -> Result<Response<Full<Bytes>>, Response<Full<Bytes>>> {
+    let Some((media_type, renderer)) = self.select(accept) else {
+      let available: Vec<&str> = self.formats.iter().map(|(m, _)| m.as_str()).collect();
+      return Err(Problem::new(406).with_extension("available", available).into_response());
+    };
+    let body = serde_json::to_value(value).map_err(|e| Problem::new(500).with_detail(e).into_response())?;
+    let bytes = renderer(&body).map_err(|e| Problem::new(500).with_detail(e).into_response())?;
+    Ok(Response::builder()
+      .status(200)
+      .header(CONTENT_TYPE, media_type.as_str())
+      .header(VARY, "accept")
+      .body(Full::new(bytes))
+      .unwrap())
+  }
+
+  pub fn pipeline<B: 'static, RT: Serialize + 'static>(&self, handler: HttpPipeline<B, RT>) -> HttpPipeline<B> {
+    let negotiator = self.clone();
+    let blocking = handler.is_blocking();
+    pipeline(move |r: Request<HttpContext<B>>| {
+      let accept = r.headers().get(ACCEPT).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+      if negotiator.select(accept.as_deref()).is_none() {
+        return negotiator.render(accept.as_deref(), &());
+      }
+      let value = handler.run(r)?;
+      negotiator.render(accept.as_deref(), &value)
+    }).blocking_if(blocking)
+  }
+
+  pub fn async_pipeline<B: Send + 'static, RT: Serialize + Send + 'static>(&self, handler: HttpAsyncPipeline<B, RT>) -> HttpAsyncPipeline<B> {
+    let negotiator = self.clone();
+    async_pipeline(move |r: Request<HttpContext<B>>| {
+      let negotiator = negotiator.clone();
+      let handler = handler.clone();
+      let accept = r.headers().get(ACCEPT).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+      async move {
+        if negotiator.select(accept.as_deref()).is_none() {
+          return negotiator.render(accept.as_deref(), &());
+        }
+        let value = handler.async_run(r).await?;
+        negotiator.render(accept.as_deref(), &value)
+      }
+    })
+  }
+}
+
+pub fn negotiate<B: 'static, RT: Serialize + 'static>(handler: HttpPipeline<B, RT>) -> HttpPipeline<B> {
+  Negotiator::default().pipeline(handler)
+}
+
+pub fn async_negotiate<B: Send + 'static, RT: Serialize + Send + 'static>(handler: HttpAsyncPipeline<B, RT>) -> HttpAsyncPipeline<B> {
+  Negotiator::default().async_pipeline(handler)
+}
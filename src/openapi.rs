@@ -0,0 +1,243 @@
+use std::{collections::BTreeMap, sync::{OnceLock, RwLock}};
+
+use http_body_util::Full;
+use hyper::{body::Bytes, header::CONTENT_TYPE, Request, Response, StatusCode};
+use schemars::{gen::SchemaSettings, JsonSchema};
+use serde_json::{json, Map, Value};
+
+use crate::{graph::PipelineNode, http_server::{HttpContext, HttpPipeline}, pipeline, AsyncPipeline, Pipeline};
+
+struct SchemaEntry {
+  reference: Value,
+  root: Value,
+  definitions: Map<String, Value>
+}
+
+fn schemas() -> &'static RwLock<BTreeMap<String, SchemaEntry>> {
+  static SCHEMAS: OnceLock<RwLock<BTreeMap<String, SchemaEntry>>> = OnceLock::new();
+  SCHEMAS.get_or_init(|| RwLock::new(BTreeMap::new()))
+}
+
+fn schema_for<T: JsonSchema>() -> String {
+  let key = std::any::type_name::<T>().to_string();
+  if !schemas().read().unwrap().contains_key(&key) {
+    let mut generator = SchemaSettings::openapi3().into_generator();
+    let reference = serde_json::to_value(generator.subschema_for::<T>()).unwrap_or(Value::Null);
+    let root = serde_json::to_value(generator.root_schema_for::<T>().schema).unwrap_or(Value::Null);
+    let definitions = generator.take_definitions().into_iter().map(|(k, v)| (k, serde_json::to_value(v).unwrap_or(Value::Null))).collect();
+    schemas().write().unwrap().insert(key.clone(), SchemaEntry { reference, root, definitions });
+  }
+  key
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Operation {
+  metadata: Vec<(String, String)>
+}
+
+impl Operation {
+  pub fn new() -> Operation {
+    Operation::default()
+  }
+
+  pub fn summary(mut self, summary: &str) -> Operation {
+    self.metadata.push(("openapi.summary".to_string(), summary.to_string()));
+    self
+  }
+
+  pub fn operation_id(mut self, id: &str) -> Operation {
+    self.metadata.push(("openapi.operation_id".to_string(), id.to_string()));
+    self
+  }
+
+  pub fn tag(mut self, tag: &str) -> Operation {
+    self.metadata.push(("openapi.tag".to_string(), tag.to_string()));
+    self
+  }
+
+  pub fn request<T: JsonSchema>(mut self) -> Operation {
+    self.metadata.push(("openapi.request".to_string(), schema_for::<T>()));
+    self
+  }
+
+  pub fn response<T: JsonSchema>(mut self, status: u16) -> Operation {
+    self.metadata.push((format!("openapi.response.{status}"), schema_for::<T>()));
+    self
+  }
+
+  pub fn query<T: JsonSchema>(mut self) -> Operation {
+    self.metadata.push(("openapi.query".to_string(), schema_for::<T>()));
+    self
+  }
+
+  pub fn empty_response(mut self, status: u16) -> Operation {
+    self.metadata.push((format!("openapi.response.{status}"), String::new()));
+    self
+  }
+
+  pub fn pipeline<VT: 'static, RT: 'static, ET: 'static>(self, pipeline: Pipeline<VT, RT, ET>) -> Pipeline<VT, RT, ET> {
+    self.metadata.iter().fold(pipeline, |p, (k, v)| p.meta(k, v))
+  }
+
+  pub fn async_pipeline<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static>(self, pipeline: AsyncPipeline<VT, RT, ET>) -> AsyncPipeline<VT, RT, ET> {
+    self.metadata.iter().fold(pipeline, |p, (k, v)| p.meta(k, v))
+  }
+}
+
+type Metadata = BTreeMap<String, String>;
+
+fn merge(lhs: &Metadata, rhs: &Metadata) -> Metadata {
+  let mut merged = lhs.clone();
+  for (k, v) in rhs.iter() {
+    if k == "http.prefix" {
+      let prefix = merged.get(k).cloned().unwrap_or_default();
+      merged.insert(k.clone(), format!("{prefix}{v}"));
+    } else {
+      merged.insert(k.clone(), v.clone());
+    }
+  }
+  merged
+}
+
+fn routes(node: &PipelineNode) -> Vec<Metadata> {
+  let own = node.metadata.clone();
+  if node.children.is_empty() {
+    return vec![own];
+  }
+  if node.kind == "or" {
+    return node.children.iter().flat_map(|child| routes(child).into_iter().map(|r| merge(&own, &r)).collect::<Vec<Metadata>>()).collect();
+  }
+  node.children.iter().fold(vec![own], |acc, child| {
+    let child_routes = routes(child);
+    acc.iter().flat_map(|a| child_routes.iter().map(|c| merge(a, c)).collect::<Vec<Metadata>>()).collect()
+  })
+}
+
+fn to_template(path: &str) -> String {
+  path.split('/').map(|segment| match segment.strip_prefix(':') {
+    Some(name) => format!("{{{name}}}"),
+    None => segment.to_string(),
+  }).collect::<Vec<String>>().join("/")
+}
+
+fn param_schema(kind: &str) -> Value {
+  match kind {
+    "int" => json!({ "type": "integer" }),
+    _ => json!({ "type": "string" }),
+  }
+}
+
+fn take_schema(key: &str, components: &mut Map<String, Value>) -> Value {
+  let schemas = schemas().read().unwrap();
+  let Some(entry) = schemas.get(key) else { return Value::Null };
+  components.extend(entry.definitions.clone());
+  entry.reference.clone()
+}
+
+fn query_parameters(key: &str, components: &mut Map<String, Value>) -> Vec<Value> {
+  let schemas = schemas().read().unwrap();
+  let Some(entry) = schemas.get(key) else { return vec![] };
+  components.extend(entry.definitions.clone());
+  let required: Vec<&str> = entry.root["required"].as_array().map(|r| r.iter().filter_map(|v| v.as_str()).collect()).unwrap_or_default();
+  entry.root["properties"].as_object().map(|properties| properties.iter().map(|(name, schema)| {
+    json!({ "name": name, "in": "query", "required": required.contains(&name.as_str()), "schema": schema })
+  }).collect()).unwrap_or_default()
+}
+
+fn path_variants(template: &str, params: &str) -> Vec<(String, Vec<(String, String)>)> {
+  let mut params: Vec<(String, String, bool)> = params.split(',').filter(|p| !p.is_empty()).map(|p| {
+    let (name, kind) = p.split_once(':').unwrap_or((p, "string"));
+    match kind.strip_suffix('?') {
+      Some(kind) => (name.to_string(), kind.to_string(), true),
+      None => (name.to_string(), kind.to_string(), false),
+    }
+  }).collect();
+  let mut template = template.to_string();
+  let mut variants = vec![(template.clone(), params.iter().map(|(n, k, _)| (n.clone(), k.clone())).collect::<Vec<_>>())];
+  while let Some((name, _, true)) = params.last().cloned() {
+    template = template.replacen(&format!("/{{{name}}}"), "", 1);
+    params.pop();
+    let template = if template.is_empty() { "/".to_string() } else { template.clone() };
+    variants.push((template, params.iter().map(|(n, k, _)| (n.clone(), k.clone())).collect()));
+  }
+  variants
+}
+
+fn operation(route: &Metadata, path_params: &[(String, String)], components: &mut Map<String, Value>) -> Value {
+  let mut op = Map::new();
+  if let Some(summary) = route.get("openapi.summary") {
+    op.insert("summary".to_string(), json!(summary));
+  }
+  if let Some(id) = route.get("openapi.operation_id") {
+    op.insert("operationId".to_string(), json!(id));
+  }
+  if let Some(tag) = route.get("openapi.tag") {
+    op.insert("tags".to_string(), json!([tag]));
+  }
+  let mut parameters: Vec<Value> = path_params.iter().map(|(name, kind)| {
+    json!({ "name": name, "in": "path", "required": true, "schema": param_schema(kind) })
+  }).collect();
+  if let Some(query) = route.get("openapi.query") {
+    parameters.extend(query_parameters(query, components));
+  }
+  if !parameters.is_empty() {
+    op.insert("parameters".to_string(), json!(parameters));
+  }
+  if let Some(schema) = route.get("openapi.request") {
+    let schema = take_schema(schema, components);
+    op.insert("requestBody".to_string(), json!({ "required": true, "content": { "application/json": { "schema": schema } } }));
+  }
+  let mut responses = Map::new();
+  for (k, v) in route.iter() {
+    if let Some(status) = k.strip_prefix("openapi.response.") {
+      let description = status.parse::<u16>().ok().and_then(|s| StatusCode::from_u16(s).ok()).and_then(|s| s.canonical_reason()).unwrap_or("");
+      let mut response = json!({ "description": description });
+      if !v.is_empty() {
+        response["content"] = json!({ "application/json": { "schema": take_schema(v, components) } });
+      }
+      responses.insert(status.to_string(), response);
+    }
+  }
+  if responses.is_empty() {
+    responses.insert("200".to_string(), json!({ "description": "OK" }));
+  }
+  op.insert("responses".to_string(), Value::Object(responses));
+  Value::Object(op)
+}
+
+pub fn document(title: &str, version: &str, node: &PipelineNode) -> Value {
+  let mut paths = Map::new();
+  let mut components = Map::new();
+  for route in routes(node).iter() {
+    let Some(path) = route.get("http.template").or(route.get("http.path")) else { continue };
+    let path = match (route.get("http.prefix"), path.as_str()) {
+      (Some(prefix), "/") => prefix.clone(),
+      (Some(prefix), path) => format!("{prefix}{path}"),
+      (None, path) => path.to_string(),
+    };
+    let method = route.get("http.method").map(|m| m.to_lowercase()).unwrap_or("get".to_string());
+    let params = route.get("http.params").cloned().unwrap_or_default();
+    for (template, path_params) in path_variants(&to_template(&path), &params) {
+      let op = operation(route, &path_params, &mut components);
+      let entry = paths.entry(template).or_insert_with(|| json!({}));
+      entry[method.as_str()] = op;
+    }
+  }
+  json!({
+    "openapi": "3.0.3",
+    "info": { "title": title, "version": version },
+    "paths": paths,
+    "components": { "schemas": components }
+  })
+}
+
+pub fn openapi_endpoint<T: 'static>(document: Value) -> HttpPipeline<T> {
+  let body = Bytes::from(document.to_string());
+  pipeline(move |_: Request<HttpContext<T>>| {
+    Ok(Response::builder()
+      .status(200)
+      .header(CONTENT_TYPE, "application/json")
+      .body(Full::new(body.clone()))
+      .unwrap())
+  })
+}
@@ -0,0 +1,145 @@
+use std::{fmt::Display, string::FromUtf8Error};
+
+use http_body_util::Full;
+use hyper::{body::Bytes, header::{HeaderValue, CONTENT_TYPE}, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::{error::Category, Map, Value};
+
+use crate::{async_pipeline, pipeline, validate::ValidationErrors, AsyncPipeline, Pipeline, RawAsyncPipeline, RawPipeline};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+  #[serde(rename = "type")]
+  pub problem_type: String,
+  pub title: String,
+  pub status: u16,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub detail: Option<String>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub instance: Option<String>,
+  #[serde(flatten)]
+  pub extensions: Map<String, Value>
+}
+
+impl Problem {
+  pub fn new(status: u16) -> Problem {
+    let title = StatusCode::from_u16(status).ok().and_then(|s| s.canonical_reason()).unwrap_or("Unknown Error");
+    Problem {
+      problem_type: "about:blank".to_string(),
+      title: title.to_string(),
+      status,
+      detail: None,
+      instance: None,
+      extensions: Map::new()
+    }
+  }
+
+  pub fn with_type(self, problem_type: &str) -> Problem {
+    Problem {
+      problem_type: problem_type.to_string(),
+      ..self
+    }
+  }
+
+  pub fn with_title(self, title: &str) -> Problem {
+    Problem {
+      title: title.to_string(),
+      ..self
+    }
+  }
+
+  pub fn with_detail<D: Display>(self, detail: D) -> Problem {
+    Problem {
+      detail: Some(detail.to_string()),
+      ..self
+    }
+  }
+
+  pub fn with_instance(self, instance: &str) -> Problem {
+    Problem {
+      instance: Some(instance.to_string()),
+      ..self
+    }
+  }
+
+  pub fn with_extension<T: Serialize>(mut self, key: &str, value: T) -> Problem {
+    self.extensions.insert(key.to_string(), serde_json::to_value(value).unwrap_or(Value::Null));
+    self
+  }
+}
+
+impl Display for Problem {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match &self.detail {
+      Some(detail) => write!(f, "{} {}: {}", self.status, self.title, detail),
+      None => write!(f, "{} {}", self.status, self.title),
+    }
+  }
+}
+
+impl std::error::Error for Problem {}
+
+pub trait IntoResponse {
+  fn into_response(self) -> Response<Full<Bytes>>;
+}
+
+impl IntoResponse for Response<Full<Bytes>> {
+  fn into_response(self) -> Response<Full<Bytes>> {
+    self
+  }
+}
+
+impl IntoResponse for Problem {
+  fn into_response(self) -> Response<Full<Bytes>> {
+    let problem = match StatusCode::from_u16(self.status) {
+      Ok(_) => self,
+      Err(_) => Problem::new(500).with_detail(format!("invalid status {}", self.status)),
+    };
+    let mut response = Response::new(Full::new(Bytes::from(serde_json::to_string(&problem).unwrap_or_default())));
+    *response.status_mut() = StatusCode::from_u16(problem.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("application/problem+json"));
+    response
+  }
+}
+
+impl IntoResponse for ValidationErrors {
+  fn into_response(self) -> Response<Full<Bytes>> {
+    Problem::new(422)
+      .with_detail("request validation failed")
+      .with_extension("errors", self.errors)
+      .into_response()
+  }
+}
+
+impl IntoResponse for serde_json::Error {
+  fn into_response(self) -> Response<Full<Bytes>> {
+    match self.classify() {
+      Category::Io => Problem::new(500).with_detail(self).into_response(),
+      _ => Problem::new(400).with_detail(self).into_response(),
+    }
+  }
+}
+
+impl IntoResponse for FromUtf8Error {
+  fn into_response(self) -> Response<Full<Bytes>> {
+    Problem::new(400).with_detail(self).into_response()
+  }
+}
+
+impl From<Problem> for Response<Full<Bytes>> {
+  fn from(problem: Problem) -> Self {
+    problem.into_response()
+  }
+}
+
+pub fn error_response<VT: 'static, RT: 'static, ET: IntoResponse + 'static>(p: Pipeline<VT, RT, ET>) -> Pipeline<VT, RT, Response<Full<Bytes>>> {
+  let blocking = p.is_blocking();
+  pipeline(move |v: VT| p.run(v).map_err(|e| e.into_response())).blocking_if(blocking)
+}
+
+pub fn async_error_response<VT: Send + 'static, RT: Send + 'static, ET: IntoResponse + Send + 'static>(p: AsyncPipeline<VT, RT, ET>) -> AsyncPipeline<VT, RT, Response<Full<Bytes>>> {
+  async_pipeline(move |v: VT| {
+    let p = p.clone();
+    async move { p.async_run(v).await.map_err(|e| e.into_response()) }
+  })
+}
@@ -0,0 +1,43 @@
+use std::{any::{Any, TypeId}, collections::HashMap, fmt::Debug, sync::Arc};
+
+#[derive(Clone, Default)]
+pub struct Resources {
+  raw: Arc<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>
+}
+
+impl Resources {
+  pub fn new() -> Resources {
+    Resources::default()
+  }
+
+  pub fn with<T: Send + Sync + 'static>(self, value: T) -> Resources {
+    self.with_arc(Arc::new(value))
+  }
+
+  pub fn with_arc<T: Send + Sync + 'static>(mut self, value: Arc<T>) -> Resources {
+    Arc::make_mut(&mut self.raw).insert(TypeId::of::<T>(), value);
+    self
+  }
+
+  pub fn get<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+    self.raw.get(&TypeId::of::<T>()).and_then(|r| r.clone().downcast::<T>().ok())
+  }
+
+  pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+    self.raw.contains_key(&TypeId::of::<T>())
+  }
+
+  pub fn len(&self) -> usize {
+    self.raw.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.raw.is_empty()
+  }
+}
+
+impl Debug for Resources {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    f.debug_struct("Resources").field("len", &self.raw.len()).finish()
+  }
+}
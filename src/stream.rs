@@ -0,0 +1,180 @@
+use std::{sync::Mutex, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use futures::{stream::{self, BoxStream}, Stream, StreamExt};
+use tokio::{io::{AsyncBufRead, AsyncBufReadExt, BufReader}, net::TcpListener, sync::mpsc::Receiver};
+
+use crate::{async_pipeline, http_server::Shutdown, AsyncFramework, AsyncPipeline, RawAsyncFramework, RawAsyncPipeline};
+
+pub struct Source<T> {
+  stream: BoxStream<'static, T>
+}
+
+impl<T: Send + 'static> Source<T> {
+  pub fn new<S: Stream<Item = T> + Send + 'static>(stream: S) -> Source<T> {
+    Source {
+      stream: stream.boxed()
+    }
+  }
+
+  pub fn into_stream(self) -> BoxStream<'static, T> {
+    self.stream
+  }
+}
+
+pub fn channel<T: Send + 'static>(receiver: Receiver<T>) -> Source<T> {
+  Source::new(stream::unfold(receiver, |mut receiver| async move {
+    receiver.recv().await.map(|v| (v, receiver))
+  }))
+}
+
+pub fn lines<R: AsyncBufRead + Unpin + Send + 'static>(reader: R) -> Source<String> {
+  Source::new(stream::unfold(reader.lines(), |mut lines| async move {
+    match lines.next_line().await {
+      Ok(Some(line)) => Some((line, lines)),
+      Ok(None) => None,
+      Err(e) => {
+        tracing::error!(error = %e, "failed to read line");
+        None
+      }
+    }
+  }))
+}
+
+pub fn interval(period: Duration) -> Source<Instant> {
+  Source::new(stream::unfold(tokio::time::interval(period), |mut interval| async move {
+    let tick = interval.tick().await;
+    Some((tick.into_std(), interval))
+  }))
+}
+
+pub fn tcp_lines(address: &str) -> Source<String> {
+  let address = address.to_string();
+  let connections = stream::once(async move { TcpListener::bind(address).await })
+    .filter_map(|listener| async move {
+      listener.map_err(|e| tracing::error!(error = %e, "failed to bind listener")).ok()
+    })
+    .flat_map(|listener| stream::unfold(listener, |listener| async move {
+      loop {
+        match listener.accept().await {
+          Ok((socket, _)) => break Some((socket, listener)),
+          Err(e) => tracing::error!(error = %e, "failed to accept connection"),
+        }
+      }
+    }));
+  Source::new(connections.map(|socket| lines(BufReader::new(socket)).into_stream()).flatten_unordered(None))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorPolicy {
+  Skip,
+  Stop
+}
+
+pub struct StreamFramework<VT, RT, ET> {
+  source: Mutex<Option<Source<VT>>>,
+  sink: AsyncPipeline<RT, (), ET>,
+  errors: Option<AsyncPipeline<ET, (), ET>>,
+  concurrency: usize,
+  ordered: bool,
+  policy: ErrorPolicy,
+  shutdown: Option<Shutdown>
+}
+
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> StreamFramework<VT, RT, ET> {
+  pub fn new(source: Source<VT>) -> StreamFramework<VT, RT, ET> {
+    StreamFramework {
+      source: Mutex::new(Some(source)),
+      sink: async_pipeline(|_: RT| async { Ok(()) }),
+      errors: None,
+      concurrency: 1,
+      ordered: true,
+      policy: ErrorPolicy::Skip,
+      shutdown: None
+    }
+  }
+
+  pub fn sink(self, sink: AsyncPipeline<RT, (), ET>) -> StreamFramework<VT, RT, ET> {
+    StreamFramework {
+      sink,
+      ..self
+    }
+  }
+
+  pub fn error_sink(self, errors: AsyncPipeline<ET, (), ET>) -> StreamFramework<VT, RT, ET> {
+    StreamFramework {
+      errors: Some(errors),
+      ..self
+    }
+  }
+
+  pub fn concurrency(self, concurrency: usize) -> StreamFramework<VT, RT, ET> {
+    StreamFramework {
+      concurrency: concurrency.max(1),
+      ..self
+    }
+  }
+
+  pub fn ordered(self, ordered: bool) -> StreamFramework<VT, RT, ET> {
+    StreamFramework {
+      ordered,
+      ..self
+    }
+  }
+
+  pub fn on_error(self, policy: ErrorPolicy) -> StreamFramework<VT, RT, ET> {
+    StreamFramework {
+      policy,
+      ..self
+    }
+  }
+
+  pub fn shutdown(self, shutdown: Shutdown) -> StreamFramework<VT, RT, ET> {
+    StreamFramework {
+      shutdown: Some(shutdown),
+      ..self
+    }
+  }
+
+  pub fn build(self) -> AsyncFramework<VT, RT, ET> {
+    AsyncFramework::new(self)
+  }
+}
+
+#[async_trait]
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> RawAsyncFramework<VT, RT, ET> for StreamFramework<VT, RT, ET> {
+  async fn run(&self, pipeline: AsyncPipeline<VT, RT, ET>) {
+    let source = self.source.lock().unwrap().take();
+    let Some(source) = source else {
+      tracing::error!("stream source has already been consumed");
+      return;
+    };
+    let items = match self.shutdown.clone() {
+      Some(shutdown) => source.into_stream().take_until(Box::pin(async move { shutdown.wait().await })).boxed(),
+      None => source.into_stream(),
+    };
+    let pipeline = pipeline & self.sink.clone();
+    let results = items.map(|v| pipeline.async_run(v));
+    let mut results = if self.ordered {
+      results.buffered(self.concurrency).boxed()
+    } else {
+      results.buffer_unordered(self.concurrency).boxed()
+    };
+    while let Some(result) = results.next().await {
+      let Err(e) = result else { continue };
+      match &self.errors {
+        Some(errors) => if errors.async_run(e).await.is_err() {
+          tracing::warn!("stream error sink failed");
+        },
+        None => tracing::warn!("stream item failed"),
+      }
+      if self.policy == ErrorPolicy::Stop {
+        break;
+      }
+    }
+  }
+}
+
+pub fn stream_framework<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static>(source: Source<VT>) -> StreamFramework<VT, RT, ET> {
+  StreamFramework::new(source)
+}
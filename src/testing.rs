@@ -1,6 +1,8 @@
 use std::fmt::Debug;
 
-use crate::{framework, Framework};
+use async_trait::async_trait;
+
+use crate::{framework, AsyncFramework, AsyncPipeline, Framework, RawAsyncFramework, RawAsyncPipeline};
 
 pub fn test_ok<VT: Clone + 'static,RT: Clone + PartialEq + Debug + 'static,ET: 'static>(init: VT, result: RT) -> Framework<VT,RT,ET>{
   framework(move |p| {
@@ -12,4 +14,42 @@ pub fn test_error<VT: Clone + 'static,RT: 'static,ET: Clone + PartialEq + Debug
   framework(move |p| {
     assert_eq!((Ok(init.clone()) & p).err(), Some(error.clone()));
   })
-}
\ No newline at end of file
+}
+
+struct AsyncOkTest<VT, RT> {
+  init: VT,
+  result: RT
+}
+
+#[async_trait]
+impl<VT: Clone + Send + Sync + 'static, RT: PartialEq + Debug + Send + Sync + 'static, ET: Send + 'static> RawAsyncFramework<VT,RT,ET> for AsyncOkTest<VT,RT> {
+  async fn run(&self, p: AsyncPipeline<VT,RT,ET>) {
+    assert_eq!(p.async_run(self.init.clone()).await.ok().as_ref(), Some(&self.result));
+  }
+}
+
+pub fn async_test_ok<VT: Clone + Send + Sync + 'static,RT: PartialEq + Debug + Send + Sync + 'static,ET: Send + 'static>(init: VT, result: RT) -> AsyncFramework<VT,RT,ET> {
+  AsyncFramework::new(AsyncOkTest {
+    init,
+    result
+  })
+}
+
+struct AsyncErrorTest<VT, ET> {
+  init: VT,
+  error: ET
+}
+
+#[async_trait]
+impl<VT: Clone + Send + Sync + 'static, RT: Send + 'static, ET: PartialEq + Debug + Send + Sync + 'static> RawAsyncFramework<VT,RT,ET> for AsyncErrorTest<VT,ET> {
+  async fn run(&self, p: AsyncPipeline<VT,RT,ET>) {
+    assert_eq!(p.async_run(self.init.clone()).await.err().as_ref(), Some(&self.error));
+  }
+}
+
+pub fn async_test_error<VT: Clone + Send + Sync + 'static,RT: Send + 'static,ET: PartialEq + Debug + Send + Sync + 'static>(init: VT, error: ET) -> AsyncFramework<VT,RT,ET> {
+  AsyncFramework::new(AsyncErrorTest {
+    init,
+    error
+  })
+}
@@ -0,0 +1,268 @@
+use std::{future::Future, marker::PhantomData, ops::{BitAnd, BitOr}};
+
+use async_trait::async_trait;
+
+use crate::{graph::PipelineNode, AsyncPipeline, Pipeline, RawAsyncPipeline, RawPipeline};
+
+pub trait StaticPipeline {
+  type Input;
+  type Output;
+  type Error;
+
+  fn run(&self, value: Self::Input) -> Result<Self::Output, Self::Error>;
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("typed")
+  }
+
+  fn is_blocking(&self) -> bool {
+    false
+  }
+
+  fn erase(self) -> Pipeline<Self::Input, Self::Output, Self::Error> where Self: Sized + Send + Sync + 'static, Self::Input: 'static, Self::Output: 'static, Self::Error: 'static {
+    Pipeline::new(Erased(self))
+  }
+}
+
+pub trait AsyncStaticPipeline: Sync {
+  type Input: Send;
+  type Output: Send;
+  type Error: Send;
+
+  fn async_run(&self, value: Self::Input) -> impl Future<Output = Result<Self::Output, Self::Error>> + Send;
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("typed")
+  }
+
+  fn erase_async(self) -> AsyncPipeline<Self::Input, Self::Output, Self::Error> where Self: Sized + Send + 'static, Self::Input: 'static, Self::Output: 'static, Self::Error: 'static {
+    AsyncPipeline::new(Erased(self))
+  }
+}
+
+pub struct Func<F, VT, RT, ET> {
+  f: F,
+  marker: PhantomData<fn(VT) -> Result<RT, ET>>
+}
+
+impl<VT, RT, ET, F: Fn(VT) -> Result<RT, ET>> StaticPipeline for Func<F, VT, RT, ET> {
+  type Input = VT;
+  type Output = RT;
+  type Error = ET;
+
+  fn run(&self, value: VT) -> Result<RT, ET> {
+    (self.f)(value)
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("pipeline")
+  }
+}
+
+pub fn pipeline<VT, RT, ET, F: Fn(VT) -> Result<RT, ET>>(f: F) -> Func<F, VT, RT, ET> {
+  Func {
+    f,
+    marker: PhantomData
+  }
+}
+
+pub struct AsyncFunc<F, VT, RT, ET> {
+  f: F,
+  marker: PhantomData<fn(VT) -> Result<RT, ET>>
+}
+
+impl<VT: Send, RT: Send, ET: Send, FT: Future<Output = Result<RT, ET>> + Send, F: Fn(VT) -> FT + Sync> AsyncStaticPipeline for AsyncFunc<F, VT, RT, ET> {
+  type Input = VT;
+  type Output = RT;
+  type Error = ET;
+
+  fn async_run(&self, value: VT) -> impl Future<Output = Result<RT, ET>> + Send {
+    (self.f)(value)
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::leaf("async_pipeline")
+  }
+}
+
+pub fn async_pipeline<VT, RT, ET, FT: Future<Output = Result<RT, ET>>, F: Fn(VT) -> FT>(f: F) -> AsyncFunc<F, VT, RT, ET> {
+  AsyncFunc {
+    f,
+    marker: PhantomData
+  }
+}
+
+pub struct And<A, B> {
+  lhs: A,
+  rhs: B
+}
+
+impl<A: StaticPipeline, B: StaticPipeline<Input = A::Output, Error = A::Error>> StaticPipeline for And<A, B> {
+  type Input = A::Input;
+  type Output = B::Output;
+  type Error = A::Error;
+
+  fn run(&self, value: A::Input) -> Result<B::Output, A::Error> {
+    self.lhs.run(value).and_then(|v| self.rhs.run(v))
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("and", vec![StaticPipeline::node(&self.lhs), StaticPipeline::node(&self.rhs)])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.lhs.is_blocking() || self.rhs.is_blocking()
+  }
+}
+
+impl<A: AsyncStaticPipeline, B: AsyncStaticPipeline<Input = A::Output, Error = A::Error>> AsyncStaticPipeline for And<A, B> {
+  type Input = A::Input;
+  type Output = B::Output;
+  type Error = A::Error;
+
+  async fn async_run(&self, value: A::Input) -> Result<B::Output, A::Error> {
+    let v = self.lhs.async_run(value).await?;
+    self.rhs.async_run(v).await
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("and", vec![AsyncStaticPipeline::node(&self.lhs), AsyncStaticPipeline::node(&self.rhs)])
+  }
+}
+
+pub struct Or<A, B> {
+  lhs: A,
+  rhs: B
+}
+
+impl<A: StaticPipeline<Input: Clone>, B: StaticPipeline<Input = A::Input, Output = A::Output, Error = A::Error>> StaticPipeline for Or<A, B> {
+  type Input = A::Input;
+  type Output = A::Output;
+  type Error = A::Error;
+
+  fn run(&self, value: A::Input) -> Result<A::Output, A::Error> {
+    self.lhs.run(value.clone()).or_else(|_| self.rhs.run(value))
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("or", vec![StaticPipeline::node(&self.lhs), StaticPipeline::node(&self.rhs)])
+  }
+
+  fn is_blocking(&self) -> bool {
+    self.lhs.is_blocking() || self.rhs.is_blocking()
+  }
+}
+
+impl<A: AsyncStaticPipeline<Input: Clone>, B: AsyncStaticPipeline<Input = A::Input, Output = A::Output, Error = A::Error>> AsyncStaticPipeline for Or<A, B> {
+  type Input = A::Input;
+  type Output = A::Output;
+  type Error = A::Error;
+
+  async fn async_run(&self, value: A::Input) -> Result<A::Output, A::Error> {
+    match self.lhs.async_run(value.clone()).await {
+      Ok(v) => Ok(v),
+      Err(_) => self.rhs.async_run(value).await,
+    }
+  }
+
+  fn node(&self) -> PipelineNode {
+    PipelineNode::branch("or", vec![AsyncStaticPipeline::node(&self.lhs), AsyncStaticPipeline::node(&self.rhs)])
+  }
+}
+
+pub struct Dynamic<P>(pub P);
+
+impl<VT, RT, ET> StaticPipeline for Dynamic<Pipeline<VT, RT, ET>> {
+  type Input = VT;
+  type Output = RT;
+  type Error = ET;
+
+  fn run(&self, value: VT) -> Result<RT, ET> {
+    RawPipeline::run(&self.0, value)
+  }
+
+  fn node(&self) -> PipelineNode {
+    RawPipeline::node(&self.0)
+  }
+
+  fn is_blocking(&self) -> bool {
+    RawPipeline::is_blocking(&self.0)
+  }
+}
+
+impl<VT: Send + 'static, RT: Send + 'static, ET: Send + 'static> AsyncStaticPipeline for Dynamic<AsyncPipeline<VT, RT, ET>> {
+  type Input = VT;
+  type Output = RT;
+  type Error = ET;
+
+  fn async_run(&self, value: VT) -> impl Future<Output = Result<RT, ET>> + Send {
+    RawAsyncPipeline::async_run(&self.0, value)
+  }
+
+  fn node(&self) -> PipelineNode {
+    RawAsyncPipeline::node(&self.0)
+  }
+}
+
+pub fn dynamic<P>(p: P) -> Dynamic<P> {
+  Dynamic(p)
+}
+
+macro_rules! impl_operators {
+  ($name:ident<$($param:ident),*>) => {
+    impl<$($param,)* Rhs> BitAnd<Rhs> for $name<$($param),*> {
+      type Output = And<Self, Rhs>;
+
+      fn bitand(self, rhs: Rhs) -> Self::Output {
+        And {
+          lhs: self,
+          rhs
+        }
+      }
+    }
+
+    impl<$($param,)* Rhs> BitOr<Rhs> for $name<$($param),*> {
+      type Output = Or<Self, Rhs>;
+
+      fn bitor(self, rhs: Rhs) -> Self::Output {
+        Or {
+          lhs: self,
+          rhs
+        }
+      }
+    }
+  };
+}
+
+impl_operators!(Func<F, VT, RT, ET>);
+impl_operators!(AsyncFunc<F, VT, RT, ET>);
+impl_operators!(And<A, B>);
+impl_operators!(Or<A, B>);
+impl_operators!(Dynamic<P>);
+
+struct Erased<P>(P);
+
+impl<P: StaticPipeline> RawPipeline<P::Input, P::Output, P::Error> for Erased<P> {
+  fn run(&self, value: P::Input) -> Result<P::Output, P::Error> {
+    self.0.run(value)
+  }
+
+  fn node(&self) -> PipelineNode {
+    StaticPipeline::node(&self.0)
+  }
+
+  fn is_blocking(&self) -> bool {
+    StaticPipeline::is_blocking(&self.0)
+  }
+}
+
+#[async_trait]
+impl<P: AsyncStaticPipeline<Input: 'static, Output: 'static, Error: 'static>> RawAsyncPipeline<P::Input, P::Output, P::Error> for Erased<P> {
+  async fn async_run(&self, value: P::Input) -> Result<P::Output, P::Error> {
+    self.0.async_run(value).await
+  }
+
+  fn node(&self) -> PipelineNode {
+    AsyncStaticPipeline::node(&self.0)
+  }
+}
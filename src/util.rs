@@ -12,4 +12,8 @@ pub fn simple_loop<RT: 'static,ET: 'static>() -> Framework<(),RT,ET> {
 
 pub fn from_utf8() -> Pipeline<Vec<u8>, String, FromUtf8Error> {
   pipeline(|p: Vec<u8>| String::from_utf8(p))
-}
\ No newline at end of file
+}
+
+pub(crate) fn escape_quoted(value: &str) -> String {
+  value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,182 @@
+use std::{collections::HashMap, fmt::Display};
+
+use hyper::Request;
+use regex::Regex;
+use serde::Serialize;
+
+use crate::{http_server::{HttpContext, HttpPipeline, HttpRequest}, pipeline, problem::IntoResponse, Pipeline};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ValidationError {
+  pub field: String,
+  pub code: String,
+  pub message: String
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ValidationErrors {
+  pub errors: Vec<ValidationError>
+}
+
+impl Display for ValidationErrors {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let messages: Vec<String> = self.errors.iter().map(|e| format!("{}: {}", e.field, e.message)).collect();
+    write!(f, "{}", messages.join(", "))
+  }
+}
+
+impl std::error::Error for ValidationErrors {}
+
+pub trait Validate {
+  fn validate(&self, validator: &mut Validator);
+}
+
+#[derive(Debug, Default)]
+pub struct Validator {
+  errors: Vec<ValidationError>
+}
+
+impl Validator {
+  pub fn new() -> Validator {
+    Validator::default()
+  }
+
+  pub fn field<'a, T: ?Sized>(&'a mut self, name: &str, value: &'a T) -> Field<'a, T> {
+    Field {
+      validator: self,
+      name: name.to_string(),
+      value
+    }
+  }
+
+  pub fn error(&mut self, field: &str, code: &str, message: &str) {
+    self.errors.push(ValidationError {
+      field: field.to_string(),
+      code: code.to_string(),
+      message: message.to_string()
+    });
+  }
+
+  pub fn finish(self) -> Result<(), ValidationErrors> {
+    if self.errors.is_empty() {
+      Ok(())
+    } else {
+      Err(ValidationErrors { errors: self.errors })
+    }
+  }
+}
+
+pub trait HasLength {
+  fn length(&self) -> usize;
+}
+
+impl HasLength for str {
+  fn length(&self) -> usize {
+    self.chars().count()
+  }
+}
+
+impl HasLength for String {
+  fn length(&self) -> usize {
+    self.chars().count()
+  }
+}
+
+impl<T> HasLength for [T] {
+  fn length(&self) -> usize {
+    self.len()
+  }
+}
+
+impl<T> HasLength for Vec<T> {
+  fn length(&self) -> usize {
+    self.len()
+  }
+}
+
+impl<K, V> HasLength for HashMap<K, V> {
+  fn length(&self) -> usize {
+    self.len()
+  }
+}
+
+pub struct Field<'a, T: ?Sized> {
+  validator: &'a mut Validator,
+  name: String,
+  value: &'a T
+}
+
+impl<'a, T: ?Sized> Field<'a, T> {
+  pub fn custom<F: Fn(&T) -> bool>(self, f: F, code: &str, message: &str) -> Field<'a, T> {
+    if !f(self.value) {
+      self.validator.error(&self.name, code, message);
+    }
+    self
+  }
+}
+
+impl<'a, T: HasLength + ?Sized> Field<'a, T> {
+  pub fn length(self, min: usize, max: usize) -> Field<'a, T> {
+    let length = self.value.length();
+    if length < min || length > max {
+      let message = format!("length must be between {min} and {max}");
+      self.validator.error(&self.name, "length", &message);
+    }
+    self
+  }
+
+  pub fn not_empty(self) -> Field<'a, T> {
+    if self.value.length() == 0 {
+      self.validator.error(&self.name, "required", "must not be empty");
+    }
+    self
+  }
+}
+
+impl<'a, T: PartialOrd + Display> Field<'a, T> {
+  pub fn range(self, min: T, max: T) -> Field<'a, T> {
+    if self.value < &min || self.value > &max {
+      let message = format!("must be between {min} and {max}");
+      self.validator.error(&self.name, "range", &message);
+    }
+    self
+  }
+}
+
+impl<'a, T: AsRef<str> + ?Sized> Field<'a, T> {
+  pub fn matches(self, re: &Regex) -> Field<'a, T> {
+    if !re.is_match(self.value.as_ref()) {
+      let message = format!("must match {}", re.as_str());
+      self.validator.error(&self.name, "pattern", &message);
+    }
+    self
+  }
+}
+
+pub fn check<T: Validate + ?Sized>(value: &T) -> Result<(), ValidationErrors> {
+  let mut validator = Validator::new();
+  value.validate(&mut validator);
+  validator.finish()
+}
+
+pub fn validate_value<T: Validate + 'static>() -> Pipeline<T, T, ValidationErrors> {
+  pipeline(|v: T| check(&v).map(|_| v))
+}
+
+pub fn validate<T: Validate + 'static>() -> HttpPipeline<T, HttpRequest<T>> {
+  pipeline(|r: Request<HttpContext<T>>| match check(&r.body().body) {
+    Ok(_) => Ok(r),
+    Err(errors) => Err(errors.into_response()),
+  })
+}
+
+pub fn validate_params<T: 'static, F: Fn(&HashMap<String, String>, &mut Validator) + Sync + Send + 'static>(f: F) -> HttpPipeline<T, HttpRequest<T>> {
+  pipeline(move |r: Request<HttpContext<T>>| {
+    let mut validator = Validator::new();
+    f(&r.body().params, &mut validator);
+    match validator.finish() {
+      Ok(_) => Ok(r),
+      Err(errors) => Err(errors.into_response()),
+    }
+  })
+}